@@ -1,36 +1,356 @@
 use anyhow::{Error, Result};
+use chip8_core::{FaultPolicy, Quirks, Timing};
 use lexopt::Arg::{Long, Short, Value};
 use lexopt::{Parser, ValueExt};
+use std::fs::canonicalize;
+use std::ops::RangeInclusive;
+
+#[cfg(feature = "sdl")]
+use crate::display::DisplayMode;
+use crate::headless::RunLimit;
+use crate::palette::Palette;
+use crate::rom::is_source;
+
+const HZ: f64 = 3000.0;
+
+/// Window pixels per CHIP-8 pixel when no `--scale` is given.
+pub const SCALE: u32 = 10;
+
+/// Subcommands, given before the path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Disassemble,
+    /// Assemble Octo source, writing to the given path or next to the source.
+    Assemble(Option<String>),
+}
 
 pub struct Args {
     pub path: String,
-    pub hertz: Option<f64>,
+    pub command: Command,
+    pub hertz: f64,
+    /// Instructions per frame, overriding `hertz` when given.
+    pub ipf: Option<u64>,
+    pub timing: Timing,
+    pub headless: bool,
+    /// How long a headless run lasts, if given.
+    pub limit: Option<RunLimit>,
+    pub quirks: Quirks,
+    pub debug: bool,
+    pub load_state: Option<String>,
+    /// Seeds CXNN's generator. Picked at random unless given with `--seed`.
+    pub seed: u32,
+    pub record_movie: Option<String>,
+    pub play_movie: Option<String>,
+    pub trace: Option<String>,
+    /// Only instructions at these addresses are traced.
+    pub trace_range: Option<RangeInclusive<u16>>,
+    /// Keep just the last N traced instructions, written out when the
+    /// debugger stops or the emulator crashes.
+    pub trace_last: Option<usize>,
+    /// What to do when an instruction faults.
+    pub on_fault: FaultPolicy,
+    /// Config file to use instead of the one in the user's config directory.
+    #[cfg(feature = "sdl")]
+    pub config: Option<String>,
+    pub palette: Palette,
+    /// Window pixels per CHIP-8 pixel, also used for screenshots.
+    pub scale: u32,
+    /// Frame a headless run saves a screenshot at.
+    pub screenshot_at: Option<u64>,
+    /// Where that screenshot goes, if not next to the ROM.
+    pub screenshot: Option<String>,
+    #[cfg(feature = "sdl")]
+    pub display: DisplayMode,
+}
+
+impl Args {
+    /// Instructions run per 60hz frame, between timer ticks.
+    pub fn cycles_per_frame(&self) -> u64 {
+        let ipf = self.ipf.unwrap_or((self.hertz / 60.0).round() as u64);
+        self.timing.cycles_per_frame(ipf.max(1))
+    }
 }
 
 pub fn parse_args() -> Result<Args> {
     let mut path = None;
     let mut parser = Parser::from_env();
     let mut hertz: Option<f64> = None;
+    let mut ipf = None;
+    let mut timing = Timing::default();
+    let mut headless = false;
+    let mut limit: Option<RunLimit> = None;
+    let mut quirks = Quirks::default();
+    let mut debug = false;
+    let mut command = None;
+    let mut output = None;
+    let mut load_state = None;
+    let mut seed = None;
+    let mut record_movie = None;
+    let mut play_movie = None;
+    let mut trace = None;
+    let mut trace_range = None;
+    let mut trace_last = None;
+    let mut on_fault = FaultPolicy::default();
+    let mut stack_depth = None;
+    #[cfg(feature = "sdl")]
+    let mut config = None;
+    let mut palette = Palette::default();
+    let mut scale = SCALE;
+    let mut screenshot_at = None;
+    #[cfg(feature = "sdl")]
+    let mut display = DisplayMode::Normal;
+    #[cfg(feature = "sdl")]
+    let mut blend_frames = None;
     while let Some(arg) = parser.next()? {
         match arg {
             Value(val) => {
-                path = Some(val.string()?);
+                let val = val.string()?;
+                match val.as_str() {
+                    "disasm" if command.is_none() && path.is_none() => {
+                        command = Some(Command::Disassemble)
+                    }
+                    "asm" if command.is_none() && path.is_none() => {
+                        command = Some(Command::Assemble(None))
+                    }
+                    _ => path = Some(val),
+                }
             }
             Long("help") | Short('h') => {
-                println!("Usage: chip-8 PATH [--hertz=NUM]");
+                println!(
+                    "Usage: chip-8 PATH [--hertz=NUM | --ipf=NUM | --timing={}] [--quirks={}] [--stack-depth=NUM] [--debug] [--load-state=PATH] [--seed=NUM] [--record-movie=PATH | --play-movie=PATH] [--trace=PATH [--trace-range=ADDR-ADDR] [--trace-last=NUM]] [--on-fault={}] [--config=PATH] [--palette=NAME|COLOURS] [--scale=NUM] [--display=normal|blend|decay|vblank [--blend-frames=NUM]] [--headless [--frames=NUM | --cycles=NUM] [--screenshot-at=FRAME [--output=PATH]]]",
+                    Timing::NAMES.join("|"),
+                    Quirks::PRESETS.join("|"),
+                    FaultPolicy::NAMES.join("|")
+                );
+                println!("       chip-8 disasm PATH");
+                println!("       chip-8 asm PATH [--output=PATH]");
                 std::process::exit(0);
             }
             Long("hertz") => {
                 hertz = parser.value()?.parse().ok();
             }
+            Long("ipf") => {
+                ipf = Some(parser.value()?.parse()?);
+            }
+            Long("timing") => {
+                let name = parser.value()?.string()?;
+                timing = Timing::from_name(&name).ok_or_else(|| {
+                    Error::msg(format!(
+                        "unknown timing {}, expected one of {}",
+                        name,
+                        Timing::NAMES.join(", ")
+                    ))
+                })?;
+            }
+            Long("quirks") => {
+                let name = parser.value()?.string()?;
+                quirks = Quirks::preset(&name).ok_or_else(|| {
+                    Error::msg(format!(
+                        "unknown quirks preset {}, expected one of {}",
+                        name,
+                        Quirks::PRESETS.join(", ")
+                    ))
+                })?;
+            }
+            Long("stack-depth") => {
+                stack_depth = Some(parser.value()?.parse()?);
+            }
+            Long("debug") => {
+                debug = true;
+            }
+            Long("load-state") => {
+                load_state = Some(parser.value()?.string()?);
+            }
+            Long("seed") => {
+                seed = Some(parser.value()?.parse()?);
+            }
+            Long("record-movie") => {
+                record_movie = Some(parser.value()?.string()?);
+            }
+            Long("play-movie") => {
+                play_movie = Some(parser.value()?.string()?);
+            }
+            Long("trace") => {
+                trace = Some(parser.value()?.string()?);
+            }
+            Long("trace-range") => {
+                let value = parser.value()?.string()?;
+                trace_range = Some(parse_range(&value).ok_or_else(|| {
+                    Error::msg(format!(
+                        "invalid trace range {}, expected hex ADDR-ADDR",
+                        value
+                    ))
+                })?);
+            }
+            Long("trace-last") => {
+                trace_last = Some(parser.value()?.parse()?);
+            }
+            Long("on-fault") => {
+                let name = parser.value()?.string()?;
+                on_fault = FaultPolicy::from_name(&name).ok_or_else(|| {
+                    Error::msg(format!(
+                        "unknown fault policy {}, expected one of {}",
+                        name,
+                        FaultPolicy::NAMES.join(", ")
+                    ))
+                })?;
+            }
+            Long("palette") => {
+                let value = parser.value()?.string()?;
+                palette = Palette::parse(&value).ok_or_else(|| {
+                    Error::msg(format!(
+                        "invalid palette {}, expected one of {} or 2 or 4 hex colours",
+                        value,
+                        Palette::PRESETS.join(", ")
+                    ))
+                })?;
+            }
+            Long("scale") => {
+                scale = parser.value()?.parse()?;
+                if !(1..=100).contains(&scale) {
+                    return Err(Error::msg("--scale must be between 1 and 100"));
+                }
+            }
+            #[cfg(feature = "sdl")]
+            Long("display") => {
+                let name = parser.value()?.string()?;
+                display = DisplayMode::from_name(&name).ok_or_else(|| {
+                    Error::msg(format!(
+                        "unknown display mode {}, expected one of {}",
+                        name,
+                        DisplayMode::NAMES.join(", ")
+                    ))
+                })?;
+            }
+            #[cfg(feature = "sdl")]
+            Long("blend-frames") => {
+                blend_frames = Some(parser.value()?.parse()?);
+            }
+            #[cfg(feature = "sdl")]
+            Long("config") => {
+                config = Some(parser.value()?.string()?);
+            }
+            Long("screenshot-at") => {
+                screenshot_at = Some(parser.value()?.parse()?);
+            }
+            Long("output") | Short('o') => {
+                output = Some(parser.value()?.string()?);
+            }
+            Long("headless") => {
+                headless = true;
+            }
+            Long("frames") => {
+                limit = Some(RunLimit::Frames(parser.value()?.parse()?));
+            }
+            Long("cycles") => {
+                limit = Some(RunLimit::Cycles(parser.value()?.parse()?));
+            }
             _ => return Err(arg.unexpected().into()),
         }
     }
 
+    if limit.is_some() && !headless {
+        return Err(Error::msg("--frames and --cycles require --headless"));
+    }
+    if hertz.is_some() && ipf.is_some() {
+        return Err(Error::msg(
+            "--hertz and --ipf both set the speed, give only one",
+        ));
+    }
+    if trace.is_none() && (trace_range.is_some() || trace_last.is_some()) {
+        return Err(Error::msg("--trace-range and --trace-last require --trace"));
+    }
+    if timing != Timing::Flat && (hertz.is_some() || ipf.is_some()) {
+        return Err(Error::msg("--hertz and --ipf only apply to flat timing"));
+    }
+    if record_movie.is_some() && play_movie.is_some() {
+        return Err(Error::msg(
+            "--record-movie and --play-movie can't be used together",
+        ));
+    }
+    if load_state.is_some() && (record_movie.is_some() || play_movie.is_some()) {
+        return Err(Error::msg(
+            "movies start from power on and can't be used with --load-state",
+        ));
+    }
+    if let Some(stack_depth) = stack_depth {
+        if !(1..=Quirks::MAX_STACK_DEPTH).contains(&stack_depth) {
+            return Err(Error::msg(format!(
+                "--stack-depth must be between 1 and {}",
+                Quirks::MAX_STACK_DEPTH
+            )));
+        }
+        quirks.stack_depth = stack_depth;
+    }
+    #[cfg(feature = "sdl")]
+    if let Some(frames) = blend_frames {
+        if !matches!(display, DisplayMode::Blend(_)) {
+            return Err(Error::msg("--blend-frames requires --display=blend"));
+        }
+        if !(2..=16).contains(&frames) {
+            return Err(Error::msg("--blend-frames must be between 2 and 16"));
+        }
+        display = DisplayMode::Blend(frames);
+    }
+    if screenshot_at.is_some() && !headless {
+        return Err(Error::msg("--screenshot-at requires --headless"));
+    }
+    let (command, screenshot) = match (command.unwrap_or(Command::Run), output) {
+        (Command::Assemble(_), output) => (Command::Assemble(output), None),
+        (command, Some(output)) if screenshot_at.is_some() => (command, Some(output)),
+        (_, Some(_)) => {
+            return Err(Error::msg(
+                "--output is only used by asm and --screenshot-at",
+            ))
+        }
+        (command, None) => (command, None),
+    };
+    let path = path
+        .ok_or("missing argument PATH".to_string())
+        .map_err(Error::msg)?;
+    if let Command::Assemble(output) = &command {
+        // The ROM is written next to the source, which mustn't be replaced
+        if !is_source(&path) {
+            return Err(Error::msg("asm needs an Octo source file ending in .8o"));
+        }
+        let same_file = |output: &str| matches!((canonicalize(output), canonicalize(&path)), (Ok(a), Ok(b)) if a == b);
+        if output.as_deref().is_some_and(same_file) {
+            return Err(Error::msg("asm can't write the ROM over its own source"));
+        }
+    }
+
     Ok(Args {
-        path: path
-            .ok_or("missing argument PATH".to_string())
-            .map_err(Error::msg)?,
-        hertz,
+        path,
+        command,
+        hertz: hertz.unwrap_or(HZ),
+        ipf,
+        timing,
+        headless,
+        limit,
+        quirks,
+        debug,
+        load_state,
+        seed: seed.unwrap_or_else(rand::random),
+        record_movie,
+        play_movie,
+        trace,
+        trace_range,
+        trace_last,
+        on_fault,
+        #[cfg(feature = "sdl")]
+        config,
+        palette,
+        scale,
+        screenshot_at,
+        screenshot,
+        #[cfg(feature = "sdl")]
+        display,
     })
 }
+
+fn parse_range(value: &str) -> Option<RangeInclusive<u16>> {
+    let hex = |value: &str| u16::from_str_radix(value.strip_prefix("0x").unwrap_or(value), 16).ok();
+    let (start, end) = value.split_once('-')?;
+    Some(hex(start)?..=hex(end)?)
+}
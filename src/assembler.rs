@@ -0,0 +1,928 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::disassembler::PROGRAM_START;
+
+/// A problem in an Octo source file, with the line it was found on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+type Result<T> = std::result::Result<T, AssembleError>;
+
+/// Compiles Octo source to a ROM to be loaded at [`PROGRAM_START`].
+///
+/// Supports labels, `:alias`, `:const`, `:macro`, `:call`, `:byte`, the
+/// full instruction set including SUPER-CHIP and XO-CHIP, `if ... then`,
+/// `if ... begin ... else ... end` and `loop ... while ... again`. Like
+/// Octo, a program with a `main` label that isn't at the very start begins
+/// with a jump to it.
+pub fn assemble(source: &str) -> std::result::Result<Vec<u8>, AssembleError> {
+    let mut assembler = Assembler::new(tokenize(source));
+    assembler.run()?;
+    Ok(assembler.rom)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Token {
+    text: String,
+    line: usize,
+    /// How many macro expansions produced this token, 0 for source text.
+    depth: usize,
+}
+
+/// Deepest macro expansion allowed, to stop a macro that expands itself.
+const MAX_MACRO_DEPTH: usize = 64;
+
+fn tokenize(source: &str) -> Vec<Token> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(line, text)| {
+            let code = text.split('#').next().unwrap_or("");
+            code.split_whitespace().map(move |word| Token {
+                text: word.to_string(),
+                line: line + 1,
+                depth: 0,
+            })
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FixupKind {
+    /// The low 12 bits of the instruction word at the offset.
+    Address,
+    /// A whole 16-bit word, for `i := long`.
+    Long,
+}
+
+struct Fixup {
+    offset: usize,
+    label: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Something a condition compares a register against.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Key,
+    NotKey,
+}
+
+impl Comparison {
+    fn negate(self) -> Comparison {
+        match self {
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+            Comparison::Less => Comparison::GreaterOrEqual,
+            Comparison::GreaterOrEqual => Comparison::Less,
+            Comparison::Greater => Comparison::LessOrEqual,
+            Comparison::LessOrEqual => Comparison::Greater,
+            Comparison::Key => Comparison::NotKey,
+            Comparison::NotKey => Comparison::Key,
+        }
+    }
+}
+
+struct Condition {
+    register: u8,
+    comparison: Comparison,
+    operand: Option<Operand>,
+}
+
+enum Block {
+    Loop {
+        start: u16,
+        breaks: Vec<usize>,
+    },
+    If {
+        /// The jump past the `begin` branch, until `else` or `end` patches it.
+        skip_jump: Option<usize>,
+        end_jumps: Vec<usize>,
+    },
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    pos: usize,
+    rom: Vec<u8>,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, u16>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>) -> Self {
+        Assembler {
+            tokens,
+            pos: 0,
+            rom: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) -> Result<()> {
+        let defines_main = self
+            .tokens
+            .windows(2)
+            .position(|pair| pair[0].text == ":" && pair[1].text == "main");
+        if matches!(defines_main, Some(position) if position > 0) {
+            self.emit_with_fixup(0x1000, "main", FixupKind::Address);
+        }
+
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let unclosed = match block {
+                Block::Loop { .. } => "loop without again",
+                Block::If { .. } => "begin without end",
+            };
+            return Err(self.error_at(self.last_line(), unclosed));
+        }
+        self.resolve_fixups()
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let token = self.next()?;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next_name()?;
+                if self.labels.insert(name.text.clone(), self.here()).is_some() {
+                    return Err(self.error(&name, "label defined twice"));
+                }
+            }
+            ":const" => {
+                let name = self.next_name()?;
+                let value = self.next()?;
+                let value = self
+                    .number(&value)
+                    .ok_or_else(|| self.error(&value, "expected a number"))?;
+                self.constants.insert(name.text, value as u16);
+            }
+            ":alias" => {
+                let name = self.next_name()?;
+                let register = self.next_register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":macro" => self.define_macro()?,
+            ":call" => {
+                let target = self.next()?;
+                self.emit_address(0x2000, &target)?;
+            }
+            ":byte" => {
+                let value = self.next()?;
+                let byte = self.byte(&value)?;
+                self.rom.push(byte);
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "scroll-down" => {
+                let n = self.next_nibble()?;
+                self.emit(0x00C0 | n);
+            }
+            "scroll-up" => {
+                let n = self.next_nibble()?;
+                self.emit(0x00D0 | n);
+            }
+            "jump" => {
+                let target = self.next()?;
+                self.emit_address(0x1000, &target)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_address(0xB000, &target)?;
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let n = self.next_nibble()?;
+                self.emit(0xD000 | xy(x, y) | n);
+            }
+            "bcd" => self.emit_fx(0x33)?,
+            "saveflags" => self.emit_fx(0x75)?,
+            "loadflags" => self.emit_fx(0x85)?,
+            "save" | "load" => {
+                let x = self.next_register()?;
+                let store = token.text == "save";
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.next_register()?;
+                    self.emit(0x5000 | xy(x, y) | if store { 0x2 } else { 0x3 });
+                } else {
+                    self.emit(0xF000 | u16::from(x) << 8 | if store { 0x55 } else { 0x65 });
+                }
+            }
+            "plane" => {
+                let n = self.next_nibble()?;
+                self.emit(0xF001 | n << 8);
+            }
+            "audio" => self.emit(0xF002),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                let low = match token.text.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | u16::from(x) << 8 | low);
+            }
+            "i" => self.index_statement()?,
+            "if" => self.if_statement()?,
+            "else" => {
+                let end_jump = self.rom.len();
+                match self.blocks.last_mut() {
+                    Some(Block::If {
+                        skip_jump,
+                        end_jumps,
+                    }) if skip_jump.is_some() => {
+                        let skip_jump = skip_jump.take().unwrap();
+                        end_jumps.push(end_jump);
+                        self.emit(0x1000);
+                        self.patch_jump(skip_jump, &token)?;
+                    }
+                    _ => return Err(self.error(&token, "else without begin")),
+                }
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If {
+                    skip_jump,
+                    end_jumps,
+                }) => {
+                    for jump in skip_jump.into_iter().chain(end_jumps) {
+                        self.patch_jump(jump, &token)?;
+                    }
+                }
+                _ => return Err(self.error(&token, "end without begin")),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here(),
+                breaks: Vec::new(),
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                let offset = self.rom.len();
+                // Leave the loop unless the condition holds
+                self.emit_skip_unless(&condition, true);
+                let jump = self.rom.len();
+                self.emit(0x1000);
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => breaks.push(jump),
+                    None => {
+                        self.rom.truncate(offset);
+                        return Err(self.error(&token, "while outside loop"));
+                    }
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks }) => {
+                    if start > 0xFFF {
+                        return Err(self.error(&token, "address out of range"));
+                    }
+                    self.emit(0x1000 | start);
+                    for jump in breaks {
+                        self.patch_jump(jump, &token)?;
+                    }
+                }
+                _ => return Err(self.error(&token, "again without loop")),
+            },
+            _ => {
+                if let Some(x) = self.register(&token) {
+                    return self.register_statement(x);
+                }
+                if self.number(&token).is_some() {
+                    let byte = self.byte(&token)?;
+                    self.rom.push(byte);
+                    return Ok(());
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand_macro(&token);
+                }
+                if is_name(&token.text) {
+                    // A bare label is a subroutine call
+                    self.emit_address(0x2000, &token)?;
+                    return Ok(());
+                }
+                return Err(self.error(&token, "unknown statement"));
+            }
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<()> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => {
+                let value = self.next()?;
+                match value.text.as_str() {
+                    "hex" => {
+                        let x = self.next_register()?;
+                        self.emit(0xF029 | u16::from(x) << 8);
+                    }
+                    "bighex" => {
+                        let x = self.next_register()?;
+                        self.emit(0xF030 | u16::from(x) << 8);
+                    }
+                    "long" => {
+                        let target = self.next()?;
+                        self.emit(0xF000);
+                        match self.address(&target) {
+                            Some(address) => self.emit(address),
+                            None => self.emit_with_fixup(0, &target.text, FixupKind::Long),
+                        }
+                    }
+                    _ => self.emit_address(0xA000, &value)?,
+                }
+            }
+            "+=" => {
+                let x = self.next_register()?;
+                self.emit(0xF01E | u16::from(x) << 8);
+            }
+            _ => return Err(self.error(&op, "expected := or += after i")),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<()> {
+        let op = self.next()?;
+        let value = self.next()?;
+        let y = self.register(&value);
+        let vx = u16::from(x) << 8;
+        match (op.text.as_str(), y) {
+            (":=", Some(y)) => self.emit(0x8000 | xy(x, y)),
+            (":=", None) => match value.text.as_str() {
+                "random" => {
+                    let mask = self.next()?;
+                    let mask = self.byte(&mask)?;
+                    self.emit(0xC000 | vx | u16::from(mask));
+                }
+                "key" => self.emit(0xF00A | vx),
+                "delay" => self.emit(0xF007 | vx),
+                _ => {
+                    let byte = self.byte(&value)?;
+                    self.emit(0x6000 | vx | u16::from(byte));
+                }
+            },
+            ("+=", Some(y)) => self.emit(0x8004 | xy(x, y)),
+            ("+=", None) => {
+                let byte = self.byte(&value)?;
+                self.emit(0x7000 | vx | u16::from(byte));
+            }
+            ("-=", Some(y)) => self.emit(0x8005 | xy(x, y)),
+            ("-=", None) => {
+                let byte = self.byte(&value)?;
+                self.emit(0x7000 | vx | u16::from(byte.wrapping_neg()));
+            }
+            ("|=", Some(y)) => self.emit(0x8001 | xy(x, y)),
+            ("&=", Some(y)) => self.emit(0x8002 | xy(x, y)),
+            ("^=", Some(y)) => self.emit(0x8003 | xy(x, y)),
+            (">>=", Some(y)) => self.emit(0x8006 | xy(x, y)),
+            ("=-", Some(y)) => self.emit(0x8007 | xy(x, y)),
+            ("<<=", Some(y)) => self.emit(0x800E | xy(x, y)),
+            _ => return Err(self.error(&op, "unknown register operation")),
+        }
+        Ok(())
+    }
+
+    fn if_statement(&mut self) -> Result<()> {
+        let condition = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.text.as_str() {
+            // The next statement runs when the condition holds
+            "then" => self.emit_skip_unless(&condition, false),
+            "begin" => {
+                self.emit_skip_unless(&condition, true);
+                let jump = self.rom.len();
+                self.emit(0x1000);
+                self.blocks.push(Block::If {
+                    skip_jump: Some(jump),
+                    end_jumps: Vec::new(),
+                });
+            }
+            _ => return Err(self.error(&keyword, "expected then or begin")),
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let register = self.next_register()?;
+        let op = self.next()?;
+        let comparison = match op.text.as_str() {
+            "key" => {
+                return Ok(Condition {
+                    register,
+                    comparison: Comparison::Key,
+                    operand: None,
+                })
+            }
+            "-key" => {
+                return Ok(Condition {
+                    register,
+                    comparison: Comparison::NotKey,
+                    operand: None,
+                })
+            }
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return Err(self.error(&op, "unknown comparison")),
+        };
+        let value = self.next()?;
+        let operand = match self.register(&value) {
+            Some(y) => Operand::Register(y),
+            None => Operand::Byte(self.byte(&value)?),
+        };
+        Ok(Condition {
+            register,
+            comparison,
+            operand: Some(operand),
+        })
+    }
+
+    /// Emits a skip over the following instruction unless `condition` holds,
+    /// or, with `invert`, if it holds. Relational comparisons go through VF.
+    fn emit_skip_unless(&mut self, condition: &Condition, invert: bool) {
+        let comparison = if invert {
+            condition.comparison.negate()
+        } else {
+            condition.comparison
+        };
+        let vx = u16::from(condition.register) << 8;
+        match (comparison, condition.operand) {
+            (Comparison::Key, _) => self.emit(0xE0A1 | vx),
+            (Comparison::NotKey, _) => self.emit(0xE09E | vx),
+            (Comparison::Equal, Some(Operand::Byte(nn))) => self.emit(0x4000 | vx | u16::from(nn)),
+            (Comparison::NotEqual, Some(Operand::Byte(nn))) => {
+                self.emit(0x3000 | vx | u16::from(nn))
+            }
+            (Comparison::Equal, Some(Operand::Register(y))) => {
+                self.emit(0x9000 | xy(condition.register, y))
+            }
+            (Comparison::NotEqual, Some(Operand::Register(y))) => {
+                self.emit(0x5000 | xy(condition.register, y))
+            }
+            (comparison, Some(operand)) => {
+                // Load the operand into VF, then subtract so that VF ends up
+                // as the no-borrow flag for the comparison
+                match operand {
+                    Operand::Register(y) => self.emit(0x8F00 | u16::from(y) << 4),
+                    Operand::Byte(nn) => self.emit(0x6F00 | u16::from(nn)),
+                }
+                let x = u16::from(condition.register) << 4;
+                match comparison {
+                    // VF = VX - operand, flag set when VX >= operand
+                    Comparison::Less | Comparison::GreaterOrEqual => self.emit(0x8F07 | x),
+                    // VF = operand - VX, flag set when operand >= VX
+                    _ => self.emit(0x8F05 | x),
+                }
+                let flag = match comparison {
+                    Comparison::GreaterOrEqual | Comparison::LessOrEqual => 1,
+                    _ => 0,
+                };
+                // Run the next instruction only when VF == flag
+                self.emit(0x4F00 | flag);
+            }
+            (_, None) => unreachable!("only key conditions have no operand"),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.next_name()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &Token) -> Result<()> {
+        if name.depth >= MAX_MACRO_DEPTH {
+            return Err(self.error(name, "macros nested too deeply"));
+        }
+        let count = self.macros[&name.text].params.len();
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            args.push(self.next()?.text);
+        }
+        let definition = &self.macros[&name.text];
+        let expansion: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|token| {
+                let text = match definition.params.iter().position(|p| *p == token.text) {
+                    Some(index) => args[index].clone(),
+                    None => token.text.clone(),
+                };
+                // Report errors inside the expansion at the call site
+                Token {
+                    text,
+                    line: name.line,
+                    depth: name.depth + 1,
+                }
+            })
+            .collect();
+        self.tokens.splice(self.pos..self.pos, expansion);
+        Ok(())
+    }
+
+    fn resolve_fixups(&mut self) -> Result<()> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = *self.labels.get(&fixup.label).ok_or_else(|| {
+                self.error_at(fixup.line, &format!("undefined label {}", fixup.label))
+            })?;
+            let offset = fixup.offset;
+            match fixup.kind {
+                FixupKind::Address => {
+                    if address > 0xFFF {
+                        return Err(self.error_at(
+                            fixup.line,
+                            &format!("{} is out of range, use i := long", fixup.label),
+                        ));
+                    }
+                    self.rom[offset] |= (address >> 8) as u8;
+                    self.rom[offset + 1] = address as u8;
+                }
+                FixupKind::Long => {
+                    self.rom[offset] = (address >> 8) as u8;
+                    self.rom[offset + 1] = address as u8;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn here(&self) -> u16 {
+        PROGRAM_START + self.rom.len() as u16
+    }
+
+    fn emit(&mut self, word: u16) {
+        self.rom.extend(word.to_be_bytes());
+    }
+
+    fn emit_fx(&mut self, low: u16) -> Result<()> {
+        let x = self.next_register()?;
+        self.emit(0xF000 | u16::from(x) << 8 | low);
+        Ok(())
+    }
+
+    fn emit_with_fixup(&mut self, word: u16, label: &str, kind: FixupKind) {
+        let line = self
+            .tokens
+            .get(self.pos.saturating_sub(1))
+            .map_or(0, |t| t.line);
+        self.fixups.push(Fixup {
+            offset: self.rom.len(),
+            label: label.to_string(),
+            kind,
+            line,
+        });
+        self.emit(word);
+    }
+
+    /// Emits `opcode | NNN` for an address that may be a forward label.
+    fn emit_address(&mut self, opcode: u16, target: &Token) -> Result<()> {
+        match self.address(target) {
+            Some(address) if address > 0xFFF => Err(self.error(target, "address out of range")),
+            Some(address) => {
+                self.emit(opcode | address);
+                Ok(())
+            }
+            None if is_name(&target.text) => {
+                self.emit_with_fixup(opcode, &target.text, FixupKind::Address);
+                Ok(())
+            }
+            None => Err(self.error(target, "expected an address")),
+        }
+    }
+
+    /// Points a placeholder jump at the current address.
+    fn patch_jump(&mut self, offset: usize, token: &Token) -> Result<()> {
+        let address = self.here();
+        if address > 0xFFF {
+            return Err(self.error(token, "address out of range"));
+        }
+        let [high, low] = (0x1000 | address).to_be_bytes();
+        self.rom[offset] = high;
+        self.rom[offset + 1] = low;
+        Ok(())
+    }
+
+    fn address(&self, token: &Token) -> Option<u16> {
+        self.labels
+            .get(&token.text)
+            .copied()
+            .or_else(|| self.number(token).map(|n| n as u16))
+    }
+
+    fn number(&self, token: &Token) -> Option<i32> {
+        if let Some(&value) = self.constants.get(&token.text) {
+            return Some(value.into());
+        }
+        let text = token.text.as_str();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let value = if let Some(hex) = text.strip_prefix("0x") {
+            i32::from_str_radix(hex, 16).ok()?
+        } else if let Some(binary) = text.strip_prefix("0b") {
+            i32::from_str_radix(binary, 2).ok()?
+        } else {
+            text.parse().ok()?
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8> {
+        match self.number(token) {
+            Some(value) if (-128..=255).contains(&value) => Ok(value as u8),
+            Some(_) => Err(self.error(token, "value doesn't fit in a byte")),
+            None => Err(self.error(token, "expected a number")),
+        }
+    }
+
+    fn register(&self, token: &Token) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(&token.text) {
+            return Some(register);
+        }
+        let digit = token.text.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| self.error_at(self.last_line(), "unexpected end of file"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn next_name(&mut self) -> Result<Token> {
+        let token = self.next()?;
+        if !is_name(&token.text) {
+            return Err(self.error(&token, "expected a name"));
+        }
+        Ok(token)
+    }
+
+    fn next_register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        self.register(&token)
+            .ok_or_else(|| self.error(&token, "expected a register"))
+    }
+
+    fn next_nibble(&mut self) -> Result<u16> {
+        let token = self.next()?;
+        match self.number(&token) {
+            Some(n) if (0..16).contains(&n) => Ok(n as u16),
+            _ => Err(self.error(&token, "expected a number from 0 to 15")),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.get(self.pos).is_some_and(|t| t.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(&token, &format!("expected {}", text)));
+        }
+        Ok(())
+    }
+
+    fn last_line(&self) -> usize {
+        self.tokens.last().map_or(0, |token| token.line)
+    }
+
+    fn error(&self, token: &Token, message: &str) -> AssembleError {
+        self.error_at(token.line, &format!("{} at '{}'", message, token.text))
+    }
+
+    fn error_at(&self, line: usize, message: &str) -> AssembleError {
+        AssembleError {
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+fn xy(x: u8, y: u8) -> u16 {
+    u16::from(x) << 8 | u16::from(y) << 4
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        assemble(source)
+            .unwrap()
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+
+    fn error(source: &str) -> String {
+        assemble(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn assembles_rumi_like_octo() {
+        let rom = assemble(include_str!("../roms/rumi.8o")).unwrap();
+        assert_eq!(rom, include_bytes!("../roms/rumi.ch8"));
+    }
+
+    #[test]
+    fn if_then_skips_unless_the_condition_holds() {
+        assert_eq!(words("if v1 == 5 then clear"), [0x4105, 0x00E0]);
+        assert_eq!(words("if v1 != v2 then clear"), [0x5120, 0x00E0]);
+        assert_eq!(words("if v1 key then clear"), [0xE1A1, 0x00E0]);
+        assert_eq!(words("if v1 -key then clear"), [0xE19E, 0x00E0]);
+    }
+
+    #[test]
+    fn relational_conditions_go_through_vf() {
+        assert_eq!(words("if v1 < 5 then"), [0x6F05, 0x8F17, 0x4F00]);
+        assert_eq!(words("if v1 >= v2 then"), [0x8F20, 0x8F17, 0x4F01]);
+        assert_eq!(words("if v1 > 5 then"), [0x6F05, 0x8F15, 0x4F00]);
+        assert_eq!(words("if v1 <= 5 then"), [0x6F05, 0x8F15, 0x4F01]);
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        assert_eq!(
+            words("if v0 == 1 begin clear else return end"),
+            [0x3001, 0x1208, 0x00E0, 0x120A, 0x00EE]
+        );
+        assert_eq!(
+            words("if v0 == 1 begin clear end"),
+            [0x3001, 0x1206, 0x00E0]
+        );
+    }
+
+    #[test]
+    fn loop_while_again() {
+        assert_eq!(
+            words("loop v0 += 1 while v0 != 10 again"),
+            [0x7001, 0x400A, 0x1208, 0x1200]
+        );
+    }
+
+    #[test]
+    fn forward_labels() {
+        assert_eq!(
+            words(":call sub i := data i := long data : sub return : data 0x12 0x34"),
+            [0x2208, 0xA20A, 0xF000, 0x020A, 0x00EE, 0x1234]
+        );
+    }
+
+    #[test]
+    fn main_label_later_in_the_program_gets_a_jump() {
+        assert_eq!(
+            words(": helper return : main clear"),
+            [0x1204, 0x00EE, 0x00E0]
+        );
+    }
+
+    #[test]
+    fn constants_aliases_and_macros() {
+        assert_eq!(
+            words(":const speed 3 :alias x v4 x := speed x += x"),
+            [0x6403, 0x8444]
+        );
+        assert_eq!(
+            words(":macro copy a b { a := b } copy v1 v2 copy v3 v4"),
+            [0x8120, 0x8340]
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_their_line() {
+        assert_eq!(
+            error("clear\njump nowhere"),
+            "line 2: undefined label nowhere"
+        );
+        assert_eq!(
+            error("v0 := 256"),
+            "line 1: value doesn't fit in a byte at '256'"
+        );
+        assert_eq!(error("again"), "line 1: again without loop at 'again'");
+        assert_eq!(error("loop clear"), "line 1: loop without again");
+        assert_eq!(error(": a clear : a"), "line 1: label defined twice at 'a'");
+        assert_eq!(
+            error("jump 0x1000"),
+            "line 1: address out of range at '0x1000'"
+        );
+    }
+
+    #[test]
+    fn recursive_macros_are_an_error() {
+        assert_eq!(
+            error(":macro m { m }\nm"),
+            "line 2: macros nested too deeply at 'm'"
+        );
+    }
+
+    #[test]
+    fn control_flow_past_the_12_bit_range_is_an_error() {
+        let padding = "0 ".repeat(0x1068);
+        assert_eq!(
+            error(&format!("{}\nloop clear again", padding)),
+            "line 2: address out of range at 'again'"
+        );
+        assert_eq!(
+            error(&format!("if v0 == 1 begin {}\nend", padding)),
+            "line 2: address out of range at 'end'"
+        );
+    }
+}
@@ -5,7 +5,8 @@ use sdl2::{
 };
 
 pub struct AudioPlayer {
-    device: AudioDevice<SquareWave>,
+    device: AudioDevice<Buzzer>,
+    pattern: (Option<[u8; 16]>, u8),
 }
 
 impl AudioPlayer {
@@ -17,13 +18,18 @@ impl AudioPlayer {
         };
         let audio_subsystem = sdl_context.audio().map_err(Error::msg)?;
         let device = audio_subsystem
-            .open_playback(None, &AUDIO_SPEC_DESIRED, |spec| SquareWave {
+            .open_playback(None, &AUDIO_SPEC_DESIRED, |spec| Buzzer {
+                freq: spec.freq as f32,
                 phase_inc: 220.0 / spec.freq as f32,
                 phase: 0.0,
                 volume: 0.25,
+                pattern: None,
             })
             .map_err(Error::msg)?;
-        Ok(AudioPlayer { device })
+        Ok(AudioPlayer {
+            device,
+            pattern: (None, 64),
+        })
     }
 
     pub fn beep(&self) {
@@ -37,25 +43,49 @@ impl AudioPlayer {
             self.device.pause()
         }
     }
+
+    /// Switches to playing an XO-CHIP audio pattern at the given pitch, or
+    /// back to the plain tone for `None`.
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        // Locking the device stalls the audio thread, so only do it on change
+        if self.pattern == (pattern, pitch) {
+            return;
+        }
+        self.pattern = (pattern, pitch);
+        let mut buzzer = self.device.lock();
+        buzzer.pattern = pattern;
+        // XO-CHIP plays the 128 bit pattern at 4000 * 2^((pitch - 64) / 48) bits per second
+        let rate = 4000.0 * 2f32.powf((f32::from(pitch) - 64.0) / 48.0);
+        buzzer.phase_inc = match pattern {
+            Some(_) => rate / 128.0 / buzzer.freq,
+            None => 220.0 / buzzer.freq,
+        };
+    }
 }
 
-struct SquareWave {
+struct Buzzer {
+    freq: f32,
     phase_inc: f32,
     phase: f32,
     volume: f32,
+    pattern: Option<[u8; 16]>,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Buzzer {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
+            let high = match self.pattern {
+                // Step through the pattern one bit at a time, most significant first
+                Some(pattern) => {
+                    let bit = (self.phase * 128.0) as usize % 128;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                // Generate a square wave
+                None => self.phase <= 0.5,
             };
+            *x = if high { self.volume } else { -self.volume };
             self.phase = (self.phase + self.phase_inc) % 1.0;
         }
     }
@@ -0,0 +1,212 @@
+use anyhow::{Context, Error, Result};
+use sdl2::controller::Button;
+use sdl2::keyboard::Scancode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// The user's config file, as written. Everything in it is optional.
+///
+/// ```toml
+/// [keys]
+/// 5 = ["W", "Up"]
+/// 8 = "S"
+///
+/// [pad]
+/// 6 = ["a", "rightshoulder"]
+///
+/// [roms."pong.ch8".keys]
+/// 1 = "Q"
+/// C = "P"
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    keys: HashMap<String, Bindings>,
+    pad: HashMap<String, Bindings>,
+    /// Overrides for single ROMs, by file name.
+    roms: HashMap<String, RomConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RomConfig {
+    keys: HashMap<String, Bindings>,
+    pad: HashMap<String, Bindings>,
+}
+
+/// SDL key or button names for one hex key, either one name or a list of
+/// them.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Bindings {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Which keyboard keys press each of the 16 hex keys.
+pub struct KeyMap {
+    keys: [Vec<Scancode>; 16],
+}
+
+impl KeyMap {
+    pub fn is_pressed(&self, key: u8, pressed: impl Fn(Scancode) -> bool) -> bool {
+        self.keys[usize::from(key)]
+            .iter()
+            .any(|&scancode| pressed(scancode))
+    }
+
+    fn bind(&mut self, keys: &HashMap<String, Bindings>) -> Result<()> {
+        bind(&mut self.keys, keys, |name| {
+            Scancode::from_name(name)
+                .ok_or_else(|| Error::msg(format!("unknown key name {}", name)))
+        })
+    }
+}
+
+/// The usual layout, with the keypad on the left of a QWERTY keyboard:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D      Q W E R
+/// 7 8 9 E  =>  A S D F
+/// A 0 B F      Z X C V
+/// ```
+impl Default for KeyMap {
+    fn default() -> Self {
+        let layout = [
+            Scancode::X,
+            Scancode::Num1,
+            Scancode::Num2,
+            Scancode::Num3,
+            Scancode::Q,
+            Scancode::W,
+            Scancode::E,
+            Scancode::A,
+            Scancode::S,
+            Scancode::D,
+            Scancode::Z,
+            Scancode::C,
+            Scancode::Num4,
+            Scancode::R,
+            Scancode::F,
+            Scancode::V,
+        ];
+        KeyMap {
+            keys: layout.map(|scancode| vec![scancode]),
+        }
+    }
+}
+
+/// Which game controller buttons press each of the 16 hex keys.
+pub struct ButtonMap {
+    keys: [Vec<Button>; 16],
+}
+
+impl ButtonMap {
+    pub fn is_pressed(&self, key: u8, pressed: impl Fn(Button) -> bool) -> bool {
+        self.keys[usize::from(key)]
+            .iter()
+            .any(|&button| pressed(button))
+    }
+
+    fn bind(&mut self, keys: &HashMap<String, Bindings>) -> Result<()> {
+        bind(&mut self.keys, keys, |name| {
+            Button::from_string(name)
+                .ok_or_else(|| Error::msg(format!("unknown button name {}", name)))
+        })
+    }
+}
+
+/// The D-pad on 5, 7, 8 and 9, the arrows most Octo games read, with the
+/// face buttons on 6 and 4 for action and back.
+impl Default for ButtonMap {
+    fn default() -> Self {
+        let mut keys: [Vec<Button>; 16] = Default::default();
+        keys[0x5] = vec![Button::DPadUp];
+        keys[0x7] = vec![Button::DPadLeft];
+        keys[0x8] = vec![Button::DPadDown];
+        keys[0x9] = vec![Button::DPadRight];
+        keys[0x6] = vec![Button::A];
+        keys[0x4] = vec![Button::B];
+        keys[0xF] = vec![Button::Start];
+        ButtonMap { keys }
+    }
+}
+
+/// Replaces the bindings of each hex key listed in `keys`, leaving the
+/// others alone.
+fn bind<T>(
+    slots: &mut [Vec<T>; 16],
+    keys: &HashMap<String, Bindings>,
+    lookup: impl Fn(&str) -> Result<T>,
+) -> Result<()> {
+    for (key, bindings) in keys {
+        let key = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|&key| key <= 0xF)
+            .ok_or_else(|| Error::msg(format!("{} isn't a hex key, expected 0-F", key)))?;
+        let names = match bindings {
+            Bindings::One(name) => std::slice::from_ref(name),
+            Bindings::Many(names) => names.as_slice(),
+        };
+        slots[usize::from(key)] = names
+            .iter()
+            .map(|name| lookup(name))
+            .collect::<Result<_>>()?;
+    }
+    Ok(())
+}
+
+/// Settings from the config file that apply to the ROM being run.
+#[derive(Default)]
+pub struct Config {
+    pub key_map: KeyMap,
+    pub button_map: ButtonMap,
+}
+
+impl Config {
+    /// Reads the config at `path`, or at the default location if there's
+    /// no path. A missing default config is fine and gives the defaults.
+    /// Overrides for `rom_path` are applied on top of the global settings.
+    pub fn load(path: Option<&str>, rom_path: &str) -> Result<Config> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let text =
+            read_to_string(&path).with_context(|| format!("unable to read {}", path.display()))?;
+        Config::parse(&text, rom_path).with_context(|| format!("unable to load {}", path.display()))
+    }
+
+    fn parse(text: &str, rom_path: &str) -> Result<Config> {
+        let file: ConfigFile = toml::from_str(text)?;
+        let mut config = Config::default();
+        config.key_map.bind(&file.keys)?;
+        config.button_map.bind(&file.pad)?;
+        let rom = Path::new(rom_path)
+            .file_name()
+            .map(|name| name.to_string_lossy());
+        if let Some((name, rom)) = rom.and_then(|name| file.roms.get_key_value(name.as_ref())) {
+            config
+                .key_map
+                .bind(&rom.keys)
+                .and_then(|()| config.button_map.bind(&rom.pad))
+                .with_context(|| format!("in the settings for {}", name))?;
+        }
+        Ok(config)
+    }
+}
+
+/// `chip-8/config.toml` in the user's config directory.
+fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("chip-8").join("config.toml"))
+}
@@ -0,0 +1,86 @@
+use chip8_core::debugger::{format_state, Outcome};
+use chip8_core::{Cpu, Debugger};
+use std::io::{stdin, stdout, BufRead, Write};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+/// What the main loop should do with the next instruction.
+pub enum Control {
+    Run,
+    Wait,
+    Quit,
+}
+
+/// Debugger prompt on stdin, read on a separate thread so that the window
+/// keeps handling events while execution is paused.
+pub struct Console {
+    debugger: Debugger,
+    lines: Receiver<String>,
+    prompting: bool,
+}
+
+impl Console {
+    /// Starts the stdin reader. The console begins paused on the first
+    /// instruction.
+    pub fn new() -> Self {
+        let (sender, lines) = channel();
+        thread::spawn(move || {
+            for line in stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        Console {
+            debugger,
+            lines,
+            prompting: false,
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.debugger.pause();
+    }
+
+    /// Call before every tick.
+    pub fn poll(&mut self, cpu: &Cpu) -> Control {
+        if !self.debugger.should_pause(cpu) {
+            return Control::Run;
+        }
+        if !self.prompting {
+            if let Some(hit) = self.debugger.take_hit() {
+                println!("{}", hit);
+            }
+            println!("{}", format_state(cpu));
+            prompt();
+            self.prompting = true;
+        }
+        match self.lines.try_recv() {
+            Ok(line) => match self.debugger.command(&line, cpu) {
+                Outcome::Stay(output) => {
+                    if !output.is_empty() {
+                        println!("{}", output);
+                    }
+                    prompt();
+                    Control::Wait
+                }
+                Outcome::Resume => {
+                    self.prompting = false;
+                    // Poll again so the debugger sees the instruction it's
+                    // resuming from before it runs
+                    self.poll(cpu)
+                }
+                Outcome::Quit => Control::Quit,
+            },
+            Err(TryRecvError::Empty) => Control::Wait,
+            Err(TryRecvError::Disconnected) => Control::Quit,
+        }
+    }
+}
+
+fn prompt() {
+    print!("(chip-8) ");
+    let _ = stdout().flush();
+}
@@ -1,12 +1,30 @@
-use rand::{thread_rng, Rng};
-use sdl2::{keyboard::Scancode, EventPump};
 use std::{fs::read, ops::Add};
 
-use crate::renderer::{GRID_X_SIZE, GRID_Y_SIZE};
+use crate::fault::{CpuError, FaultPolicy};
+use crate::instruction::Instruction;
+use crate::keypad::Keypad;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::random::Random;
+use crate::screen::Screen;
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::timing::Timing;
+
+pub const GRID_X_SIZE: u32 = 64;
+pub const GRID_Y_SIZE: u32 = 32;
+
+const BIG_FONT_ADDRESS: u16 = 0x50;
+const MEMORY_SIZE: usize = 0x10000;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 enum OpCode {
     ClearScreen,
+    ScrollDown(u8),
+    ScrollUp(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
     Jump(u16),
     SetRegister(usize, u8),
     AddToRegister(usize, u8),
@@ -16,25 +34,61 @@ enum OpCode {
     ReturnFromSubroutine,
     Skip,
     SkipIfKey(usize, bool),
+    Or(usize, usize),
+    And(usize, usize),
+    Xor(usize, usize),
     Add(usize, usize),
     Subtract(usize, usize, usize),
-    ShiftRight(usize),
-    ShiftLeft(usize),
+    ShiftRight(usize, usize),
+    ShiftLeft(usize, usize),
     SetDelayTimer(u8),
     SetSoundTimer(u8),
     GetKey(usize),
     BinaryConversion(usize),
     StoreMemory(usize),
     LoadMemory(usize),
+    StoreFlags(usize),
+    LoadFlags(usize),
+    StoreRange(usize, usize),
+    LoadRange(usize, usize),
+    SelectPlanes(u8),
+    LoadAudioPattern,
+    SetPitch(usize),
     NoOp,
-    Unknown,
+    Unknown(u16),
+}
+
+/// Whether a [`MemoryAccess`] read or wrote memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
 }
 
-#[derive(Debug)]
+/// A run of memory that an instruction read or wrote as data, rather than
+/// fetched as code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    /// Address of the instruction that made the access.
+    pub pc: u16,
+    pub address: u16,
+    pub len: u16,
+    pub kind: Access,
+}
+
+impl MemoryAccess {
+    /// Whether any byte from `start` to `end` inclusive was touched.
+    pub fn overlaps(&self, start: u16, end: u16) -> bool {
+        let last = u32::from(self.address) + u32::from(self.len) - 1;
+        u32::from(start) <= last && u32::from(self.address) <= u32::from(end)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Cpu {
     pc: u16,
     i: u16,
-    memory: [u16; 4096],
+    memory: Vec<u16>,
     stack: Vec<u16>,
     registers: [u8; 16],
     delay_timer: u8,
@@ -42,7 +96,20 @@ pub struct Cpu {
     awaiting_key_register: Option<usize>,
     key_down: Option<u8>,
     screen_mutated: bool,
-    pub screen: [[u8; 64]; 32],
+    awaiting_vblank: bool,
+    halted: bool,
+    rpl_flags: [u8; 16],
+    planes: u8,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    quirks: Quirks,
+    random: Random,
+    timing: Timing,
+    fault_policy: FaultPolicy,
+    cycles: u32,
+    instruction_address: u16,
+    accesses: Vec<MemoryAccess>,
+    pub screen: Screen,
 }
 
 const FONT: [u16; 80] = [
@@ -64,12 +131,31 @@ const FONT: [u16; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+const BIG_FONT: [u16; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 impl Default for Cpu {
     fn default() -> Self {
         let mut cpu = Cpu {
             pc: 0x200,
             i: 0x0,
-            memory: [0u16; 4096],
+            memory: vec![0u16; MEMORY_SIZE],
             stack: Vec::new(),
             registers: [0u8; 16],
             delay_timer: 0u8,
@@ -77,11 +163,27 @@ impl Default for Cpu {
             awaiting_key_register: None,
             key_down: None,
             screen_mutated: false,
-            screen: [[0; GRID_X_SIZE as usize]; GRID_Y_SIZE as usize],
+            awaiting_vblank: false,
+            halted: false,
+            rpl_flags: [0u8; 16],
+            planes: 1,
+            audio_pattern: None,
+            pitch: 64,
+            quirks: Quirks::default(),
+            random: Random::default(),
+            timing: Timing::default(),
+            fault_policy: FaultPolicy::default(),
+            cycles: 0,
+            instruction_address: 0x200,
+            accesses: Vec::new(),
+            screen: Screen::default(),
         };
         FONT.iter().enumerate().for_each(|(i, &x)| {
             cpu.memory[i] = x;
         });
+        BIG_FONT.iter().enumerate().for_each(|(i, &x)| {
+            cpu.memory[usize::from(BIG_FONT_ADDRESS) + i] = x;
+        });
         cpu
     }
 }
@@ -91,44 +193,112 @@ impl Cpu {
         Default::default()
     }
 
-    pub fn load(mut self, path: String) -> Cpu {
-        let bytes = read(std::path::PathBuf::from(path)).unwrap();
+    pub fn load(self, path: String) -> Result<Cpu, CpuError> {
+        let bytes = read(std::path::PathBuf::from(path))?;
+        self.load_rom(&bytes)
+    }
+
+    pub fn load_rom(mut self, bytes: &[u8]) -> Result<Cpu, CpuError> {
+        let max = MEMORY_SIZE - 0x200;
+        if bytes.len() > max {
+            return Err(CpuError::RomTooLarge {
+                size: bytes.len(),
+                max,
+            });
+        }
         bytes.iter().enumerate().for_each(|(i, &x)| {
             self.memory[0x200 + i] = x.into();
         });
+        Ok(self)
+    }
+
+    pub fn with_quirks(mut self, quirks: Quirks) -> Cpu {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Swaps the generator CXNN draws from, for instance to seed it.
+    pub fn with_random(mut self, random: Random) -> Cpu {
+        self.random = random;
+        self
+    }
+
+    pub fn with_timing(mut self, timing: Timing) -> Cpu {
+        self.timing = timing;
+        self
+    }
+
+    pub fn with_fault_policy(mut self, fault_policy: FaultPolicy) -> Cpu {
+        self.fault_policy = fault_policy;
         self
     }
 
-    pub fn tick(&mut self, event_pump: &EventPump) {
+    /// Runs one instruction, or waits a step for a frame or a key. Faults are
+    /// handled according to the CPU's [`FaultPolicy`].
+    pub fn tick(&mut self, keypad: &mut dyn Keypad) -> Result<(), CpuError> {
+        // Waiting on a frame or a key costs the least it can, so the
+        // scheduler's budget runs down without overshooting
+        self.cycles = 1;
+        self.accesses.clear();
+        if self.awaiting_vblank || self.halted {
+            return Ok(());
+        }
         if let Some(register) = self.awaiting_key_register {
-            let keyboard_state = event_pump.keyboard_state();
-            if let Some(key) = keyboard_state.pressed_scancodes().next() {
-                self.key_down = Some(self.unmap(key));
-                return;
-            }
-            if let Some(key_down) = self.key_down {
-                if !keyboard_state
-                    .pressed_scancodes()
-                    .any(|scancode| scancode == self.map(key_down))
-                {
-                    self.set_register(register, key_down);
-                    self.awaiting_key_register = None;
-                    self.key_down = None;
+            // FX0A completes on release, so wait for a key to go down and
+            // then come back up
+            let released = match self.key_down {
+                Some(key_down) if !keypad.is_pressed(key_down) => Some(key_down),
+                Some(_) => None,
+                None => {
+                    self.key_down = (0..16).find(|&key| keypad.is_pressed(key));
+                    keypad.take_released()
                 }
+            };
+            if let Some(key) = released {
+                self.set_register(register, key);
+                self.awaiting_key_register = None;
+                self.key_down = None;
             }
         } else {
+            self.instruction_address = self.pc;
             let instruction = self.fetch();
+            self.cycles = self
+                .timing
+                .cost(Instruction::decode(instruction), &self.registers);
             let opcode = self.decode(instruction);
-            self.execute(opcode, event_pump);
+            let result = self.execute(opcode, keypad);
 
             match opcode {
-                OpCode::ClearScreen | OpCode::Draw(..) => self.screen_mutated = true,
+                OpCode::ClearScreen
+                | OpCode::ScrollDown(_)
+                | OpCode::ScrollUp(_)
+                | OpCode::ScrollRight
+                | OpCode::ScrollLeft
+                | OpCode::LowRes
+                | OpCode::HighRes
+                | OpCode::Draw(..) => self.screen_mutated = true,
                 _ => self.screen_mutated = false,
             }
+            if let Err(error) = result {
+                return self.fault(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn fault(&mut self, error: CpuError) -> Result<(), CpuError> {
+        match self.fault_policy {
+            FaultPolicy::Halt => {
+                self.halted = true;
+                Err(error)
+            }
+            FaultPolicy::Warn => Err(error),
+            FaultPolicy::Ignore => Ok(()),
         }
     }
 
     pub fn tick_timers(&mut self) {
+        self.awaiting_vblank = false;
         self.delay_timer = self.delay_timer.saturating_sub(1);
         self.sound_timer = self.sound_timer.saturating_sub(1);
     }
@@ -141,6 +311,174 @@ impl Cpu {
         self.screen_mutated
     }
 
+    /// The XO-CHIP audio pattern loaded with F002, if any. Until one is
+    /// loaded the buzzer should play a plain tone.
+    pub fn audio_pattern(&self) -> Option<[u8; 16]> {
+        self.audio_pattern
+    }
+
+    /// The XO-CHIP playback pitch set with FX3A.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// What the last [`tick`](Cpu::tick) cost under the CPU's [`Timing`].
+    /// Schedulers add these up against a frame's cycle budget.
+    pub fn cycles(&self) -> u32 {
+        self.cycles
+    }
+
+    /// Memory the last [`tick`](Cpu::tick) read or wrote as data, for
+    /// watchpoints.
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
+    /// Whether the program has exited with 00FD.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn index(&self) -> u16 {
+        self.i
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn memory(&self) -> &[u16] {
+        &self.memory
+    }
+
+    /// Serializes the whole machine state. Quirks are configuration rather
+    /// than state, so aren't included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u16(self.pc);
+        writer.u16(self.i);
+        let memory: Vec<u8> = self.memory.iter().map(|&byte| byte as u8).collect();
+        writer.bytes(&memory);
+        writer.u8(self.stack.len() as u8);
+        self.stack.iter().for_each(|&address| writer.u16(address));
+        writer.bytes(&self.registers);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.optional_u8(self.awaiting_key_register.map(|register| register as u8));
+        writer.optional_u8(self.key_down);
+        writer.bool(self.awaiting_vblank);
+        writer.bool(self.halted);
+        writer.bytes(&self.rpl_flags);
+        writer.u8(self.planes);
+        writer.bool(self.audio_pattern.is_some());
+        writer.bytes(&self.audio_pattern.unwrap_or_default());
+        writer.u8(self.pitch);
+        self.random.save_state(&mut writer);
+        self.screen.save_state(&mut writer);
+        writer.finish()
+    }
+
+    /// Restores a state written by [`save_state`](Cpu::save_state). On error
+    /// the CPU is left untouched.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(bytes)?;
+        let pc = reader.u16()?;
+        let i = reader.u16()?;
+        let memory = reader.bytes()?;
+        if memory.len() != MEMORY_SIZE {
+            return Err(StateError::Invalid("memory"));
+        }
+        let stack_len = reader.u8()?;
+        if usize::from(stack_len) > self.quirks.stack_depth {
+            return Err(StateError::Invalid("stack"));
+        }
+        let stack = (0..stack_len)
+            .map(|_| reader.u16())
+            .collect::<Result<Vec<u16>, StateError>>()?;
+        let registers = reader.array("registers")?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let awaiting_key_register = reader.optional_u8()?.map(usize::from);
+        if awaiting_key_register.is_some_and(|register| register > 0xF) {
+            return Err(StateError::Invalid("key register"));
+        }
+        let key_down = reader.optional_u8()?;
+        let awaiting_vblank = reader.bool()?;
+        let halted = reader.bool()?;
+        let rpl_flags = reader.array("flags")?;
+        let planes = reader.u8()?;
+        let has_audio_pattern = reader.bool()?;
+        let audio_pattern = Some(reader.array("audio pattern")?).filter(|_| has_audio_pattern);
+        let pitch = reader.u8()?;
+        let random = Random::load_state(&mut reader)?;
+        let screen = Screen::load_state(&mut reader)?;
+        reader.finish()?;
+
+        self.pc = pc;
+        self.i = i;
+        self.memory = memory.iter().map(|&byte| byte.into()).collect();
+        self.stack = stack;
+        self.registers = registers;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.awaiting_key_register = awaiting_key_register;
+        self.key_down = key_down;
+        self.screen_mutated = true;
+        self.awaiting_vblank = awaiting_vblank;
+        self.halted = halted;
+        self.rpl_flags = rpl_flags;
+        self.planes = planes;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.random = random;
+        self.screen = screen;
+        Ok(())
+    }
+
+    /// Return addresses, innermost call last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// How many calls can be nested before the stack overflows.
+    pub fn stack_depth(&self) -> usize {
+        self.quirks.stack_depth
+    }
+
+    /// The instruction word at `pc`, which the next tick will execute.
+    pub fn current_instruction(&self) -> u16 {
+        self.read_current_instruction()
+    }
+
+    /// Whether execution is blocked on FX0A waiting for a key.
+    pub fn awaiting_key(&self) -> bool {
+        self.awaiting_key_register.is_some()
+    }
+
+    /// Whether a draw is holding up execution until the next frame, under
+    /// the display wait quirk or VIP timing.
+    pub fn awaiting_vblank(&self) -> bool {
+        self.awaiting_vblank
+    }
+
+    /// Whether the next tick will wait for a frame or a key, or do nothing
+    /// at all, rather than execute the instruction at `pc`.
+    pub fn is_waiting(&self) -> bool {
+        self.awaiting_vblank || self.awaiting_key() || self.halted
+    }
+
     fn fetch(&mut self) -> u16 {
         let instruction = self.read_current_instruction();
         self.skip();
@@ -157,6 +495,13 @@ impl Cpu {
 
         match (kind, x, y, n) {
             (0x0, 0x0, 0xE, 0x0) => OpCode::ClearScreen,
+            (0x0, 0x0, 0xC, n) => OpCode::ScrollDown(n as u8),
+            (0x0, 0x0, 0xD, n) => OpCode::ScrollUp(n as u8),
+            (0x0, 0x0, 0xF, 0xB) => OpCode::ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => OpCode::ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => OpCode::Exit,
+            (0x0, 0x0, 0xF, 0xE) => OpCode::LowRes,
+            (0x0, 0x0, 0xF, 0xF) => OpCode::HighRes,
             (0x1, _, _, _) => OpCode::Jump(nnn),
             (0x0, 0x0, 0xE, 0xE) => OpCode::ReturnFromSubroutine,
             (0x2, _, _, _) => OpCode::CallSubroutine(nnn),
@@ -172,6 +517,8 @@ impl Cpu {
                 }
                 OpCode::NoOp
             }
+            (0x5, x, y, 0x2) => OpCode::StoreRange(x, y),
+            (0x5, x, y, 0x3) => OpCode::LoadRange(x, y),
             (0x5, x, y, 0x0) => {
                 if self.get_register(x) == self.get_register(y) {
                     return OpCode::Skip;
@@ -187,115 +534,209 @@ impl Cpu {
             (0x6, x, _, _) => OpCode::SetRegister(x, nn),
             (0x7, x, _, _) => OpCode::AddToRegister(x, nn),
             (0x8, x, y, 0x0) => OpCode::SetRegister(x, self.get_register(y)),
-            (0x8, x, y, 0x1) => OpCode::SetRegister(
-                x,
-                (usize::from(self.get_register(x)) | usize::from(self.get_register(y)))
-                    .try_into()
-                    .unwrap(),
-            ),
-            (0x8, x, y, 0x2) => OpCode::SetRegister(
-                x,
-                (usize::from(self.get_register(x)) & usize::from(self.get_register(y)))
-                    .try_into()
-                    .unwrap(),
-            ),
-            (0x8, x, y, 0x3) => OpCode::SetRegister(
-                x,
-                (usize::from(self.get_register(x)) ^ usize::from(self.get_register(y)))
-                    .try_into()
-                    .unwrap(),
-            ),
+            (0x8, x, y, 0x1) => OpCode::Or(x, y),
+            (0x8, x, y, 0x2) => OpCode::And(x, y),
+            (0x8, x, y, 0x3) => OpCode::Xor(x, y),
             (0x8, x, y, 0x4) => OpCode::Add(x, y),
             (0x8, x, y, 0x5) => OpCode::Subtract(x, y, x),
-            (0x8, x, _, 0x6) => OpCode::ShiftRight(x),
+            (0x8, x, y, 0x6) => OpCode::ShiftRight(x, y),
             (0x8, x, y, 0x7) => OpCode::Subtract(y, x, x),
-            (0x8, x, _, 0xE) => OpCode::ShiftLeft(x),
+            (0x8, x, y, 0xE) => OpCode::ShiftLeft(x, y),
             (0xA, _, _, _) => OpCode::SetIndex(nnn),
-            (0xB, _, _, _) => OpCode::Jump(nnn.add(u16::from(self.get_register(0)))),
-            (0xC, x, _, _) => OpCode::SetRegister(x, thread_rng().gen::<u8>() & nn),
+            (0xB, x, _, _) => {
+                let offset = if self.quirks.jumping { x } else { 0 };
+                OpCode::Jump(nnn.add(u16::from(self.get_register(offset))))
+            }
+            (0xC, x, _, _) => OpCode::SetRegister(x, self.random.next_byte() & nn),
             (0xD, x, y, n) => OpCode::Draw(x, y, n),
             (0xE, x, 0x9, 0xE) => OpCode::SkipIfKey(x, true),
             (0xE, x, 0xA, 0x1) => OpCode::SkipIfKey(x, false),
             (0xF, x, 0x0, 0x7) => OpCode::SetRegister(x, self.delay_timer),
             (0xF, x, 0x1, 0x5) => OpCode::SetDelayTimer(self.get_register(x)),
             (0xF, x, 0x1, 0x8) => OpCode::SetSoundTimer(self.get_register(x)),
-            (0xF, x, 0x1, 0xE) => {
-                OpCode::SetIndex(self.get_index().add(u16::from(self.get_register(x))))
-            }
+            (0xF, x, 0x1, 0xE) => OpCode::SetIndex(
+                self.get_index()
+                    .wrapping_add(u16::from(self.get_register(x))),
+            ),
             (0xF, x, 0x0, 0xA) => OpCode::GetKey(x),
-            (0xF, x, 0x2, 0x9) => OpCode::SetIndex(self.get_register(x).into()),
+            (0xF, 0x0, 0x0, 0x0) => OpCode::SetIndex(self.fetch()),
+            (0xF, n, 0x0, 0x1) => OpCode::SelectPlanes(n as u8),
+            (0xF, 0x0, 0x0, 0x2) => OpCode::LoadAudioPattern,
+            (0xF, x, 0x3, 0xA) => OpCode::SetPitch(x),
+            (0xF, x, 0x2, 0x9) => OpCode::SetIndex(u16::from(self.get_register(x) & 0xF) * 5),
+            (0xF, x, 0x3, 0x0) => {
+                OpCode::SetIndex(BIG_FONT_ADDRESS + u16::from(self.get_register(x) & 0xF) * 10)
+            }
             (0xF, x, 0x3, 0x3) => OpCode::BinaryConversion(x),
             (0xF, x, 0x5, 0x5) => OpCode::StoreMemory(x),
             (0xF, x, 0x6, 0x5) => OpCode::LoadMemory(x),
+            (0xF, x, 0x7, 0x5) => OpCode::StoreFlags(x),
+            (0xF, x, 0x8, 0x5) => OpCode::LoadFlags(x),
             (0x0, _, _, _) => OpCode::NoOp,
-            _ => OpCode::Unknown,
+            _ => OpCode::Unknown(instruction),
         }
     }
 
-    fn execute(&mut self, opcode: OpCode, event_pump: &EventPump) {
+    fn execute(&mut self, opcode: OpCode, keypad: &dyn Keypad) -> Result<(), CpuError> {
         match opcode {
-            OpCode::ClearScreen => self.clear_screen(),
+            OpCode::ClearScreen => self.screen.clear(self.planes),
+            OpCode::ScrollDown(n) => self.screen.scroll_down(self.planes, n.into()),
+            OpCode::ScrollUp(n) => self.screen.scroll_up(self.planes, n.into()),
+            OpCode::ScrollRight => self.screen.scroll_right(self.planes, 4),
+            OpCode::ScrollLeft => self.screen.scroll_left(self.planes, 4),
+            OpCode::Exit => self.halted = true,
+            OpCode::LowRes => self.screen.set_hires(false),
+            OpCode::HighRes => self.screen.set_hires(true),
             OpCode::Jump(n) => self.set_pc(n),
             OpCode::SetRegister(x, n) => self.set_register(x, n),
             OpCode::AddToRegister(x, n) => self.add_to_register(x, n),
-            OpCode::Draw(x, y, n) => self.update_screen(x, y, n),
+            OpCode::Draw(x, y, n) => {
+                self.update_screen(x, y, n);
+                self.awaiting_vblank = self.quirks.display_wait || self.timing.waits_for_vblank();
+            }
             OpCode::SetIndex(n) => self.set_index(n),
-            OpCode::CallSubroutine(n) => self.call_subroutine(n),
-            OpCode::ReturnFromSubroutine => self.return_from_subroutine(),
-            OpCode::Skip => self.skip(),
+            OpCode::CallSubroutine(n) => self.call_subroutine(n)?,
+            OpCode::ReturnFromSubroutine => self.return_from_subroutine()?,
+            OpCode::Skip => self.skip_instruction(),
             OpCode::SkipIfKey(x, pressed) => {
                 let key = self.get_register(x);
-                self.skip_if_key(key, pressed, event_pump);
+                self.skip_if_key(key, pressed, keypad);
             }
+            OpCode::Or(x, y) => self.logical(x, y, |vx, vy| vx | vy),
+            OpCode::And(x, y) => self.logical(x, y, |vx, vy| vx & vy),
+            OpCode::Xor(x, y) => self.logical(x, y, |vx, vy| vx ^ vy),
             OpCode::Add(x, y) => self.add(x, y),
             OpCode::Subtract(x, y, n) => self.subtract(x, y, n),
-            OpCode::ShiftRight(x) => self.shift_right(x),
-            OpCode::ShiftLeft(x) => self.shift_left(x),
+            OpCode::ShiftRight(x, y) => self.shift_right(x, y),
+            OpCode::ShiftLeft(x, y) => self.shift_left(x, y),
             OpCode::SetDelayTimer(x) => self.set_delay_timer(x),
             OpCode::SetSoundTimer(x) => self.set_sound_timer(x),
             OpCode::GetKey(key) => self.set_waiting_key(Some(key)),
-            OpCode::BinaryConversion(x) => self.binary_conversion(x),
-            OpCode::StoreMemory(x) => self.store_memory(x),
-            OpCode::LoadMemory(x) => self.load_memory(x),
+            OpCode::BinaryConversion(x) => self.binary_conversion(x)?,
+            OpCode::StoreMemory(x) => self.store_memory(x)?,
+            OpCode::LoadMemory(x) => self.load_memory(x)?,
+            OpCode::StoreFlags(x) => self.rpl_flags[..=x].copy_from_slice(&self.registers[..=x]),
+            OpCode::LoadFlags(x) => self.registers[..=x].copy_from_slice(&self.rpl_flags[..=x]),
+            OpCode::StoreRange(x, y) => self.store_range(x, y)?,
+            OpCode::LoadRange(x, y) => self.load_range(x, y)?,
+            OpCode::SelectPlanes(n) => self.planes = n,
+            OpCode::LoadAudioPattern => self.load_audio_pattern()?,
+            OpCode::SetPitch(x) => self.pitch = self.get_register(x),
             OpCode::NoOp => (),
-            OpCode::Unknown => (),
+            OpCode::Unknown(opcode) => {
+                return Err(CpuError::UnknownOpcode {
+                    pc: self.instruction_address,
+                    opcode,
+                })
+            }
         };
+        Ok(())
     }
 
-    fn skip_if_key(&mut self, key: u8, pressed: bool, event_pump: &EventPump) -> bool {
-        let key_pressed = event_pump
-            .keyboard_state()
-            .is_scancode_pressed(self.map(key));
+    fn skip_if_key(&mut self, key: u8, pressed: bool, keypad: &dyn Keypad) -> bool {
+        let key_pressed = keypad.is_pressed(key);
         if pressed == key_pressed {
-            self.skip();
+            self.skip_instruction();
         }
         pressed == key_pressed
     }
 
-    fn store_memory(&mut self, register: usize) {
-        let index = self.get_index();
+    fn store_memory(&mut self, register: usize) -> Result<(), CpuError> {
+        let index = self.memory_range(register + 1)?;
+        self.record_access(Access::Write, index as u16, register + 1);
         for x in 0..register + 1 {
             let vx = u16::from(self.get_register(x));
-            self.memory[usize::from(index) + x] = vx;
+            self.memory[index + x] = vx;
         }
+        self.increment_index_after_memory(register);
+        Ok(())
     }
 
-    fn load_memory(&mut self, register: usize) {
-        let index = self.get_index();
+    fn load_memory(&mut self, register: usize) -> Result<(), CpuError> {
+        let index = self.memory_range(register + 1)?;
+        self.record_access(Access::Read, index as u16, register + 1);
         for x in 0..register + 1 {
-            let value: u8 = self.memory[usize::from(index) + x].try_into().unwrap();
+            let value = self.memory[index + x] as u8;
             self.set_register(x, value);
         }
+        self.increment_index_after_memory(register);
+        Ok(())
     }
 
-    fn binary_conversion(&mut self, register: usize) {
+    fn store_range(&mut self, x: usize, y: usize) -> Result<(), CpuError> {
+        let index = self.memory_range(x.abs_diff(y) + 1)?;
+        self.record_access(Access::Write, index as u16, x.abs_diff(y) + 1);
+        Self::register_range(x, y)
+            .enumerate()
+            .for_each(|(offset, register)| {
+                self.memory[index + offset] = self.registers[register].into();
+            });
+        Ok(())
+    }
+
+    fn load_range(&mut self, x: usize, y: usize) -> Result<(), CpuError> {
+        let index = self.memory_range(x.abs_diff(y) + 1)?;
+        self.record_access(Access::Read, index as u16, x.abs_diff(y) + 1);
+        Self::register_range(x, y)
+            .enumerate()
+            .for_each(|(offset, register)| {
+                self.registers[register] = self.memory[index + offset] as u8;
+            });
+        Ok(())
+    }
+
+    /// Checks that `len` bytes from I are all in memory, returning I as an
+    /// index.
+    fn memory_range(&self, len: usize) -> Result<usize, CpuError> {
+        let index = usize::from(self.i);
+        if index + len > MEMORY_SIZE {
+            return Err(CpuError::MemoryOutOfRange {
+                pc: self.instruction_address,
+                address: index + len - 1,
+            });
+        }
+        Ok(index)
+    }
+
+    /// Registers X to Y inclusive, counting down if Y is below X.
+    fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    fn load_audio_pattern(&mut self) -> Result<(), CpuError> {
+        let index = self.memory_range(16)?;
+        self.record_access(Access::Read, index as u16, 16);
+        let mut pattern = [0u8; 16];
+        pattern.iter_mut().enumerate().for_each(|(offset, byte)| {
+            *byte = self.memory[index + offset] as u8;
+        });
+        self.audio_pattern = Some(pattern);
+        Ok(())
+    }
+
+    fn increment_index_after_memory(&mut self, register: usize) {
+        let increment = match self.quirks.memory_increment {
+            MemoryIncrement::None => return,
+            MemoryIncrement::X => register,
+            MemoryIncrement::XPlusOne => register + 1,
+        };
+        self.set_index(self.get_index().wrapping_add(increment as u16));
+    }
+
+    fn binary_conversion(&mut self, register: usize) -> Result<(), CpuError> {
         let value = self.get_register(register);
         let (ones, tens, hundreds) = (value % 10, (value / 10) % 10, (value / 10) / 10);
 
-        let index = self.get_index();
-        self.memory[usize::from(index)] = hundreds.into();
-        self.memory[usize::from(index + 1)] = tens.into();
-        self.memory[usize::from(index + 2)] = ones.into();
+        let index = self.memory_range(3)?;
+        self.record_access(Access::Write, index as u16, 3);
+        self.memory[index] = hundreds.into();
+        self.memory[index + 1] = tens.into();
+        self.memory[index + 2] = ones.into();
+        Ok(())
     }
 
     fn set_waiting_key(&mut self, register: Option<usize>) {
@@ -310,6 +751,15 @@ impl Cpu {
         self.sound_timer = val;
     }
 
+    fn logical(&mut self, x: usize, y: usize, op: fn(u8, u8) -> u8) {
+        let vx = self.get_register(x);
+        let vy = self.get_register(y);
+        self.set_register(x, op(vx, vy));
+        if self.quirks.vf_reset {
+            self.set_carry(0);
+        }
+    }
+
     fn add(&mut self, x: usize, y: usize) {
         let vx = self.get_register(x);
         let vy = self.get_register(y);
@@ -325,33 +775,51 @@ impl Cpu {
         let vx = self.get_register(x);
         let vy = self.get_register(y);
         self.set_register(destination, vx.wrapping_sub(vy));
-        if vx > vy {
+        // VF is the no-borrow flag, so equal values set it too
+        if vx >= vy {
             self.set_carry(1);
         } else {
             self.set_carry(0);
         }
     }
 
-    fn shift_right(&mut self, x: usize) {
-        // Shift the value of VX one bit to the right
-        let vx = self.get_register(x);
+    fn shift_right(&mut self, x: usize, y: usize) {
+        // Shift the value of VX (or VY, on the original VIP) one bit to the right
+        let vx = self.shift_source(x, y);
         self.set_register(x, vx >> 1);
 
         // Set VF to 1 if the bit that was shifted out was 1, or 0 if it was 0
         self.set_carry(vx & 0b0001);
     }
 
-    fn shift_left(&mut self, x: usize) {
-        // Shift the value of VX one bit to the left
-        let vx = self.get_register(x);
+    fn shift_left(&mut self, x: usize, y: usize) {
+        // Shift the value of VX (or VY, on the original VIP) one bit to the left
+        let vx = self.shift_source(x, y);
         self.set_register(x, vx << 1);
 
         // Set VF to 1 if the bit that was shifted out was 1, or 0 if it was 0
-        self.set_carry((vx & 0b1000) >> 3);
+        self.set_carry((vx & 0b1000_0000) >> 7);
+    }
+
+    fn shift_source(&mut self, x: usize, y: usize) -> u8 {
+        if self.quirks.shifting {
+            self.get_register(x)
+        } else {
+            self.get_register(y)
+        }
     }
 
     fn skip(&mut self) {
-        self.pc += 2
+        self.pc = self.pc.wrapping_add(2)
+    }
+
+    // Conditional skips step over the whole of the next instruction, which
+    // is two words long for the XO-CHIP F000 NNNN long index load
+    fn skip_instruction(&mut self) {
+        if self.read_current_instruction() == 0xF000 {
+            self.skip();
+        }
+        self.skip();
     }
 
     fn get_index(&self) -> u16 {
@@ -366,15 +834,30 @@ impl Cpu {
         self.pc = value
     }
 
-    fn return_from_subroutine(&mut self) {
-        let last_address = self.stack.pop().unwrap();
-        self.set_pc(last_address)
+    fn return_from_subroutine(&mut self) -> Result<(), CpuError> {
+        let last_address = self.stack.pop().ok_or(CpuError::StackUnderflow {
+            pc: self.instruction_address,
+        })?;
+        self.set_pc(last_address);
+        Ok(())
     }
 
-    fn call_subroutine(&mut self, address: u16) {
+    fn call_subroutine(&mut self, address: u16) -> Result<(), CpuError> {
+        if self.stack.len() >= self.quirks.stack_depth {
+            return Err(CpuError::StackOverflow {
+                pc: self.instruction_address,
+                // Each return address is just past the call that pushed it
+                calls: self
+                    .stack
+                    .iter()
+                    .map(|address| address.wrapping_sub(2))
+                    .collect(),
+            });
+        }
         let current_pc = self.pc;
         self.stack.push(current_pc);
-        self.set_pc(address)
+        self.set_pc(address);
+        Ok(())
     }
 
     fn get_register(&mut self, register: usize) -> u8 {
@@ -393,106 +876,546 @@ impl Cpu {
         self.set_register(register, self.registers[register].wrapping_add(value));
     }
 
-    fn read_current_instruction(&mut self) -> u16 {
+    fn read_current_instruction(&self) -> u16 {
         let part_one = self.memory[self.pc as usize];
-        let part_two = self.memory[(self.pc + 1) as usize];
+        let part_two = self.memory[self.pc.wrapping_add(1) as usize];
         (part_one << 8) | part_two
     }
 
-    fn clear_screen(&mut self) {
-        self.screen.iter_mut().for_each(|row| {
-            row.iter_mut().for_each(|pixel| {
-                *pixel = 0;
-            })
-        });
+    fn record_access(&mut self, kind: Access, address: u16, len: usize) {
+        if len > 0 {
+            self.accesses.push(MemoryAccess {
+                pc: self.instruction_address,
+                address,
+                len: len as u16,
+                kind,
+            });
+        }
     }
 
     fn update_screen(&mut self, x: usize, y: usize, n: u16) {
-        let mut x_coord = self.get_register(x) % 64;
-        let mut y_coord = self.get_register(y) % 32;
+        // DXY0 draws a 16x16 sprite, stored as two bytes per row
+        let (sprite_width, sprite_height) = if n == 0 { (16, 16) } else { (8, n) };
+        let sprite_size = sprite_height * sprite_width as u16 / 8;
+        // The starting position always wraps, only the sprite itself clips
+        let x_start = usize::from(self.get_register(x)) % self.screen.width();
+        let y_start = usize::from(self.get_register(y)) % self.screen.height();
         self.set_carry(0);
-        for sprite_row in 0..n {
-            let sprite_index = (self.get_index() + sprite_row) as usize;
 
-            // get nth sprite counting from memory address in I
-            let sprite_byte = self.memory[sprite_index] as u8;
+        // With several XO-CHIP planes selected, the sprite for each plane
+        // follows on from the last in memory
+        let mut sprite_address = self.get_index();
+        for plane in [0b01, 0b10] {
+            if self.planes & plane != 0 {
+                self.draw_sprite(
+                    x_start,
+                    y_start,
+                    plane,
+                    sprite_address,
+                    (sprite_width, sprite_height),
+                );
+                sprite_address = sprite_address.wrapping_add(sprite_size);
+            }
+        }
+    }
 
-            // For each of the 8 pixels/bits in this sprite row (from left to right, ie. from most to least significant bit):
-            for bit in 0..8 {
-                let sprite_pixel = sprite_byte & (0x80u8 >> bit);
-                let screen_pixel = &mut self.screen[y_coord as usize][x_coord as usize];
+    fn draw_sprite(
+        &mut self,
+        x_start: usize,
+        y_start: usize,
+        plane: u8,
+        address: u16,
+        (sprite_width, sprite_height): (usize, u16),
+    ) {
+        let width = self.screen.width();
+        let height = self.screen.height();
+        let bytes_per_row = sprite_width / 8;
+        // Clipped rows are never read, so watchpoints on them shouldn't fire
+        let rows_read = if self.quirks.clipping {
+            usize::from(sprite_height).min(height - y_start)
+        } else {
+            usize::from(sprite_height)
+        };
+        self.record_access(Access::Read, address, rows_read * bytes_per_row);
+        for sprite_row in 0..sprite_height {
+            let mut y_coord = y_start + sprite_row as usize;
+            if y_coord >= height {
+                // Stop if you reach the bottom edge of the screen
+                if self.quirks.clipping {
+                    break;
+                }
+                y_coord %= height;
+            }
+            // get nth sprite row counting from the sprite's address
+            let sprite_index = usize::from(address) + sprite_row as usize * bytes_per_row;
+            let sprite_bits = (0..bytes_per_row).fold(0u16, |bits, byte| {
+                (bits << 8) | (self.memory[(sprite_index + byte) % MEMORY_SIZE] & 0xFF)
+            });
+
+            // For each pixel/bit in this sprite row (from left to right, ie. from most to least significant bit):
+            for bit in 0..sprite_width {
+                let mut x_coord = x_start + bit;
+                if x_coord >= width {
+                    // If you reach the right edge of the screen, stop drawing this row
+                    if self.quirks.clipping {
+                        break;
+                    }
+                    x_coord %= width;
+                }
+                let sprite_pixel = sprite_bits & (1 << (sprite_width - 1 - bit));
+                let screen_pixel = self.screen.pixel_mut(x_coord, y_coord);
 
                 // If the current pixel in the sprite row is on and the pixel at coordinates X,Y on the screen is also on, turn off the pixel and set VF to 1
                 if sprite_pixel != 0 {
-                    if *screen_pixel == 1 {
-                        *screen_pixel = 0;
+                    if *screen_pixel & plane != 0 {
+                        *screen_pixel &= !plane;
                         self.set_carry(1);
                     } else {
                         // Or if the current pixel in the sprite row is on and the screen pixel is not, draw the pixel at the X and Y coordinates
-                        *screen_pixel = 1;
+                        *screen_pixel |= plane;
                     }
                 }
-                // If you reach the right edge of the screen, stop drawing this row
-                if x_coord == 63 {
-                    break;
-                }
-                // Increment X (VX is not incremented)
-                x_coord += 1;
-                // END FOR
             }
-            x_coord = self.get_register(x) % 64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::{NullKeypad, ScriptedKeypad};
+    use crate::state::StateError;
+
+    fn cpu(rom: &[u16]) -> Cpu {
+        let bytes: Vec<u8> = rom.iter().flat_map(|word| word.to_be_bytes()).collect();
+        Cpu::new().load_rom(&bytes).unwrap()
+    }
 
-            // Increment Y (VY is not incremented)
-            y_coord += 1;
-            // Stop if you reach the bottom edge of the screen
-            if y_coord == 31 {
-                break;
+    fn keys(pressed: &[u8]) -> [bool; 16] {
+        let mut keys = [false; 16];
+        pressed
+            .iter()
+            .for_each(|&key| keys[usize::from(key)] = true);
+        keys
+    }
+
+    /// Lit pixels as (x, y), row by row.
+    fn lit(screen: &Screen) -> Vec<(usize, usize)> {
+        (0..screen.height())
+            .flat_map(|y| (0..screen.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| screen.pixel(x, y) != 0)
+            .collect()
+    }
+
+    fn cpu_with(quirks: Quirks, rom: &[u16]) -> Cpu {
+        cpu(rom).with_quirks(quirks)
+    }
+
+    fn run(cpu: &mut Cpu, keypad: &mut dyn Keypad, ticks: usize) {
+        (0..ticks).for_each(|_| cpu.tick(keypad).unwrap());
+    }
+
+    #[test]
+    fn get_key_completes_on_release() {
+        let mut cpu = cpu(&[0xF30A, 0x1202]);
+        let mut keypad = ScriptedKeypad::new(vec![keys(&[]), keys(&[5]), keys(&[5]), keys(&[])]);
+        run(&mut cpu, &mut keypad, 2);
+        assert!(cpu.awaiting_key());
+
+        // Held down, so still waiting
+        keypad.advance();
+        run(&mut cpu, &mut keypad, 1);
+        keypad.advance();
+        run(&mut cpu, &mut keypad, 1);
+        assert!(cpu.awaiting_key());
+        assert_eq!(cpu.registers()[3], 0);
+
+        keypad.advance();
+        run(&mut cpu, &mut keypad, 1);
+        assert!(!cpu.awaiting_key());
+        assert_eq!(cpu.registers()[3], 5);
+    }
+
+    #[test]
+    fn skips_on_key_state() {
+        // v2 := 1 runs unless key 5 is held, v3 := 1 only while it is
+        let rom = [0x6105, 0xE19E, 0x6201, 0xE1A1, 0x6301, 0x120A];
+
+        let mut held = cpu(&rom);
+        run(&mut held, &mut ScriptedKeypad::new(vec![keys(&[5])]), 5);
+        assert_eq!(held.registers()[2..4], [0, 1]);
+
+        let mut released = cpu(&rom);
+        run(&mut released, &mut ScriptedKeypad::new(vec![keys(&[4])]), 5);
+        assert_eq!(released.registers()[2..4], [1, 0]);
+    }
+
+    #[test]
+    fn add_to_index_wraps() {
+        let mut cpu = cpu(&[0xF000, 0xFFFF, 0x6005, 0xF01E]);
+        run(&mut cpu, &mut NullKeypad, 3);
+        assert_eq!(cpu.index(), 0x0004);
+    }
+
+    #[test]
+    fn subtraction_sets_vf_when_there_is_no_borrow() {
+        let mut cpu = cpu(&[0x6005, 0x6105, 0x8015, 0x8F00, 0x8107, 0x120A]);
+        run(&mut cpu, &mut NullKeypad, 3);
+        assert_eq!(cpu.registers()[0xF], 1);
+        run(&mut cpu, &mut NullKeypad, 2);
+        assert_eq!(cpu.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn save_state_round_trips() {
+        // Draw a digit, then call a subroutine that waits on a key
+        let rom = [0x6A07, 0xF029, 0xD005, 0x220A, 0x1208, 0xF10A];
+        let mut original = cpu(&rom);
+        run(&mut original, &mut NullKeypad, 6);
+        let state = original.save_state();
+
+        let mut restored = cpu(&[]);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.pc(), original.pc());
+        assert_eq!(restored.stack(), [0x208]);
+        assert_eq!(restored.registers()[0xA], 7);
+        assert_eq!(restored.screen, original.screen);
+        assert!(restored.awaiting_key());
+
+        // Both finish the FX0A the same way
+        for cpu in [&mut original, &mut restored] {
+            let mut keypad = ScriptedKeypad::new(vec![keys(&[2]), keys(&[])]);
+            run(cpu, &mut keypad, 1);
+            keypad.advance();
+            run(cpu, &mut keypad, 1);
+            assert_eq!(cpu.registers()[1], 2);
+        }
+        assert_eq!(original.save_state(), restored.save_state());
+    }
+
+    #[test]
+    fn bad_states_leave_the_cpu_untouched() {
+        let mut cpu = cpu(&[0x6A07]);
+        run(&mut cpu, &mut NullKeypad, 1);
+        let before = cpu.save_state();
+
+        let truncated = &before[..before.len() - 1];
+        assert_eq!(cpu.load_state(truncated), Err(StateError::Truncated));
+        assert_eq!(cpu.load_state(b"nope"), Err(StateError::NotAState));
+        assert_eq!(cpu.save_state(), before);
+
+        // A stack deeper than this CPU allows
+        let mut deep = cpu_with(Quirks::SUPER_CHIP, &[0x2200]);
+        run(&mut deep, &mut NullKeypad, 13);
+        let mut vip = cpu_with(Quirks::COSMAC_VIP, &[]);
+        assert_eq!(
+            vip.load_state(&deep.save_state()),
+            Err(StateError::Invalid("stack"))
+        );
+    }
+
+    #[test]
+    fn presets_are_found_by_name() {
+        let presets = Quirks::PRESETS.map(|name| Quirks::preset(name).unwrap());
+        assert_eq!(
+            presets,
+            [
+                Quirks::COSMAC_VIP,
+                Quirks::CHIP_48,
+                Quirks::SUPER_CHIP,
+                Quirks::MODERN
+            ]
+        );
+        assert_eq!(Quirks::preset("cosmac"), None);
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        let rom = [0x6F05, 0x6001, 0x6102, 0x8011];
+        for (quirks, vf) in [(Quirks::COSMAC_VIP, 0), (Quirks::SUPER_CHIP, 5)] {
+            let mut cpu = cpu_with(quirks, &rom);
+            run(&mut cpu, &mut NullKeypad, 4);
+            assert_eq!(cpu.registers()[0xF], vf, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn memory_increment_quirk() {
+        for opcode in [0xF155, 0xF165] {
+            let rom = [0xA300, opcode];
+            for (quirks, i) in [
+                (Quirks::SUPER_CHIP, 0x300),
+                (Quirks::CHIP_48, 0x301),
+                (Quirks::COSMAC_VIP, 0x302),
+            ] {
+                let mut cpu = cpu_with(quirks, &rom);
+                run(&mut cpu, &mut NullKeypad, 2);
+                assert_eq!(cpu.index(), i, "{:04x} {:?}", opcode, quirks);
+            }
+        }
+    }
+
+    #[test]
+    fn shifting_quirk() {
+        // v0 = 3, v1 = 6, then shift into v0
+        for (opcode, vip, schip) in [(0x8016, (3, 0), (1, 1)), (0x801E, (12, 0), (6, 0))] {
+            let rom = [0x6003, 0x6106, opcode];
+            for (quirks, (v0, vf)) in [(Quirks::COSMAC_VIP, vip), (Quirks::SUPER_CHIP, schip)] {
+                let mut cpu = cpu_with(quirks, &rom);
+                run(&mut cpu, &mut NullKeypad, 3);
+                assert_eq!(cpu.registers()[0], v0, "{:04x} {:?}", opcode, quirks);
+                assert_eq!(cpu.registers()[0xF], vf, "{:04x} {:?}", opcode, quirks);
             }
         }
     }
 
-    fn map(&self, code: u8) -> Scancode {
-        match code {
-            0x00 => Scancode::X,
-            0x01 => Scancode::Num1,
-            0x02 => Scancode::Num2,
-            0x03 => Scancode::Num3,
-            0x04 => Scancode::Q,
-            0x05 => Scancode::W,
-            0x06 => Scancode::E,
-            0x07 => Scancode::A,
-            0x08 => Scancode::S,
-            0x09 => Scancode::D,
-            0x0A => Scancode::Z,
-            0x0B => Scancode::C,
-            0x0C => Scancode::Num4,
-            0x0D => Scancode::R,
-            0x0E => Scancode::F,
-            0x0F => Scancode::V,
-            _ => Scancode::Escape,
+    #[test]
+    fn jumping_quirk() {
+        let rom = [0x6004, 0x6308, 0xB300];
+        for (quirks, pc) in [(Quirks::COSMAC_VIP, 0x304), (Quirks::CHIP_48, 0x308)] {
+            let mut cpu = cpu_with(quirks, &rom);
+            run(&mut cpu, &mut NullKeypad, 3);
+            assert_eq!(cpu.pc(), pc, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        let rom = [0xD005, 0x6001];
+        for (quirks, waits) in [(Quirks::COSMAC_VIP, true), (Quirks::MODERN, false)] {
+            let mut cpu = cpu_with(quirks, &rom);
+            run(&mut cpu, &mut NullKeypad, 2);
+            assert_eq!(cpu.registers()[0] == 0, waits, "{:?}", quirks);
+
+            // The wait ends on the next frame
+            cpu.tick_timers();
+            run(&mut cpu, &mut NullKeypad, 1);
+            assert_eq!(cpu.registers()[0], 1, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn clipping_quirk() {
+        // The 0 glyph's top row is four pixels wide, drawn from x = 62
+        let rom = [0x603E, 0xD015];
+        for (quirks, wrapped) in [(Quirks::COSMAC_VIP, 0), (Quirks::MODERN, 1)] {
+            let mut cpu = cpu_with(quirks, &rom);
+            run(&mut cpu, &mut NullKeypad, 2);
+            assert_eq!(cpu.screen.pixel(63, 0), 1, "{:?}", quirks);
+            assert_eq!(cpu.screen.pixel(0, 0), wrapped, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn hires_switches_resolution_and_clears() {
+        let mut cpu = cpu(&[0xD015, 0x00FF, 0x00FE]);
+        run(&mut cpu, &mut NullKeypad, 2);
+        assert_eq!((cpu.screen.width(), cpu.screen.height()), (128, 64));
+        assert!(lit(&cpu.screen).is_empty());
+        run(&mut cpu, &mut NullKeypad, 1);
+        assert_eq!((cpu.screen.width(), cpu.screen.height()), (64, 32));
+    }
+
+    #[test]
+    fn scrolls_in_each_direction() {
+        // Light the single pixel in the top left of the 1 glyph
+        let mut cpu = cpu(&[
+            0x6001, 0xF029, 0x6002, 0xD001, 0x00C3, 0x00FB, 0x00D1, 0x00FC,
+        ]);
+        run(&mut cpu, &mut NullKeypad, 4);
+        assert_eq!(lit(&cpu.screen), [(4, 2)]);
+        for expected in [(4, 5), (8, 5), (8, 4), (4, 4)] {
+            run(&mut cpu, &mut NullKeypad, 1);
+            assert_eq!(lit(&cpu.screen), [expected]);
         }
     }
 
-    fn unmap(&self, code: Scancode) -> u8 {
-        match code {
-            Scancode::X => 0x00,
-            Scancode::Num1 => 0x01,
-            Scancode::Num2 => 0x02,
-            Scancode::Num3 => 0x03,
-            Scancode::Q => 0x04,
-            Scancode::W => 0x05,
-            Scancode::E => 0x06,
-            Scancode::A => 0x07,
-            Scancode::S => 0x08,
-            Scancode::D => 0x09,
-            Scancode::Z => 0x0A,
-            Scancode::C => 0x0B,
-            Scancode::Num4 => 0x0C,
-            Scancode::R => 0x0D,
-            Scancode::F => 0x0E,
-            Scancode::V => 0x0F,
-            Scancode::Escape => 0x29,
-            _ => 0x29,
+    #[test]
+    fn scrolling_drops_pixels_off_the_edge() {
+        let mut cpu = cpu(&[0x603E, 0xD015, 0x00FB]);
+        run(&mut cpu, &mut NullKeypad, 3);
+        assert!(lit(&cpu.screen).iter().all(|&(x, _)| x < 4));
+    }
+
+    #[test]
+    fn draws_16x16_sprites() {
+        let mut rom = vec![0x00FF, 0xA20C, 0x6130, 0xD010, 0xD010, 0x120A];
+        rom.extend([0xFFFF; 16]);
+        let mut cpu = cpu(&rom);
+        run(&mut cpu, &mut NullKeypad, 4);
+        let pixels = lit(&cpu.screen);
+        assert_eq!(pixels.len(), 16 * 16);
+        assert_eq!(pixels[0], (0, 48));
+        assert_eq!(pixels[255], (15, 63));
+        assert_eq!(cpu.registers()[0xF], 0);
+
+        // Drawing it again erases it, reporting the collision
+        run(&mut cpu, &mut NullKeypad, 1);
+        assert!(lit(&cpu.screen).is_empty());
+        assert_eq!(cpu.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn big_font_and_flags() {
+        let mut cpu = cpu(&[
+            0x6008, 0xF030, 0x6107, 0xF175, 0x6000, 0x6100, 0xF185, 0x00FD,
+        ]);
+        run(&mut cpu, &mut NullKeypad, 2);
+        assert_eq!(cpu.index(), BIG_FONT_ADDRESS + 80);
+        run(&mut cpu, &mut NullKeypad, 6);
+        assert_eq!(cpu.registers()[..2], [8, 7]);
+        assert!(cpu.halted());
+    }
+
+    #[test]
+    fn skips_step_over_long_index_loads() {
+        let mut skip = cpu(&[0x3000, 0xF000, 0x1234, 0x6001]);
+        run(&mut skip, &mut NullKeypad, 2);
+        assert_eq!(skip.registers()[0], 1);
+        assert_eq!(skip.index(), 0);
+
+        let mut long = cpu(&[0xF000, 0x1234]);
+        run(&mut long, &mut NullKeypad, 1);
+        assert_eq!((long.index(), long.pc()), (0x1234, 0x204));
+    }
+
+    #[test]
+    fn draws_each_selected_plane_from_consecutive_sprites() {
+        // Plane 1 gets the left half of the row, plane 2 the right
+        let mut cpu = cpu(&[0xF301, 0xA20A, 0xD001, 0xF201, 0xD001, 0xF00F]);
+        run(&mut cpu, &mut NullKeypad, 3);
+        let row: Vec<u8> = (0..8).map(|x| cpu.screen.pixel(x, 0)).collect();
+        assert_eq!(row, [1, 1, 1, 1, 2, 2, 2, 2]);
+
+        // Plane 2 alone reads the first sprite
+        run(&mut cpu, &mut NullKeypad, 2);
+        let row: Vec<u8> = (0..8).map(|x| cpu.screen.pixel(x, 0)).collect();
+        assert_eq!(row, [3, 3, 3, 3, 2, 2, 2, 2]);
+        assert_eq!(cpu.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn saves_and_loads_register_ranges() {
+        // Store v1 to v3 at 0x300, then load them back reversed into v4 to v6
+        let rom = [0x6101, 0x6202, 0x6303, 0xA300, 0x5132, 0x5643];
+        let mut cpu = cpu(&rom);
+        run(&mut cpu, &mut NullKeypad, 6);
+        assert_eq!(cpu.memory()[0x300..0x303], [1, 2, 3]);
+        assert_eq!(cpu.registers()[4..7], [3, 2, 1]);
+        // Unlike FX55 and FX65, I never moves
+        assert_eq!(cpu.index(), 0x300);
+    }
+
+    #[test]
+    fn loads_the_audio_pattern_and_pitch() {
+        let mut rom = vec![0xA20A, 0xF002, 0x6AC0, 0xFA3A, 0x1208];
+        rom.extend([0x0102; 8]);
+        let mut cpu = cpu(&rom);
+        assert_eq!(cpu.audio_pattern(), None);
+        run(&mut cpu, &mut NullKeypad, 4);
+        assert_eq!(
+            cpu.audio_pattern(),
+            Some([1, 2].repeat(8).try_into().unwrap())
+        );
+        assert_eq!(cpu.pitch(), 0xC0);
+    }
+
+    #[test]
+    fn unknown_opcodes_fault() {
+        let mut cpu = cpu(&[0x8008]);
+        assert!(matches!(
+            cpu.tick(&mut NullKeypad),
+            Err(CpuError::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0x8008
+            })
+        ));
+    }
+
+    #[test]
+    fn memory_accesses_past_the_end_fault() {
+        // Saving two registers from 0xFFFF would write to 0x10000
+        let mut cpu = cpu(&[0xF000, 0xFFFF, 0xF155]);
+        run(&mut cpu, &mut NullKeypad, 1);
+        assert!(matches!(
+            cpu.tick(&mut NullKeypad),
+            Err(CpuError::MemoryOutOfRange {
+                pc: 0x204,
+                address: 0x10000
+            })
+        ));
+    }
+
+    #[test]
+    fn roms_must_fit_above_0x200() {
+        assert!(Cpu::new().load_rom(&[0; 0xFE00]).is_ok());
+        assert!(matches!(
+            Cpu::new().load_rom(&[0; 0xFE01]),
+            Err(CpuError::RomTooLarge {
+                size: 0xFE01,
+                max: 0xFE00
+            })
+        ));
+    }
+
+    #[test]
+    fn fault_policies() {
+        // An unknown opcode, then v0 += 1
+        let faulting = |policy| cpu(&[0x8008, 0x7001]).with_fault_policy(policy);
+
+        let mut halt = faulting(FaultPolicy::Halt);
+        assert!(halt.tick(&mut NullKeypad).is_err());
+        assert!(halt.halted());
+        assert_eq!(halt.pc(), 0x202);
+        run(&mut halt, &mut NullKeypad, 1);
+        assert_eq!((halt.pc(), halt.registers()[0]), (0x202, 0));
+
+        // The rest skip the faulting instruction and carry on
+        let mut warn = faulting(FaultPolicy::Warn);
+        assert!(warn.tick(&mut NullKeypad).is_err());
+        assert!(!warn.halted());
+        assert_eq!(warn.pc(), 0x202);
+        run(&mut warn, &mut NullKeypad, 1);
+        assert_eq!((warn.pc(), warn.registers()[0]), (0x204, 1));
+
+        let mut ignore = faulting(FaultPolicy::Ignore);
+        assert!(ignore.tick(&mut NullKeypad).is_ok());
+        assert!(!ignore.halted());
+        assert_eq!(ignore.pc(), 0x202);
+        run(&mut ignore, &mut NullKeypad, 1);
+        assert_eq!((ignore.pc(), ignore.registers()[0]), (0x204, 1));
+    }
+
+    #[test]
+    fn calls_nest_as_deep_as_the_stack_depth() {
+        // Each call calls itself again
+        for quirks in [Quirks::COSMAC_VIP, Quirks::SUPER_CHIP] {
+            let mut cpu = cpu_with(quirks, &[0x6001, 0x2202]);
+            run(&mut cpu, &mut NullKeypad, 1 + quirks.stack_depth);
+            assert_eq!(cpu.stack().len(), quirks.stack_depth);
+
+            let error = cpu.tick(&mut NullKeypad).unwrap_err();
+            let CpuError::StackOverflow { pc, calls } = &error else {
+                panic!("expected a stack overflow, got {:?}", error);
+            };
+            assert_eq!(*pc, 0x202);
+            // Every call on the stack was made from 0x202
+            assert_eq!(*calls, vec![0x202; quirks.stack_depth]);
+            assert!(error.to_string().starts_with(&format!(
+                "0x0202: call with the stack full at {} levels, called from 0x0202 > ",
+                quirks.stack_depth
+            )));
         }
     }
+
+    #[test]
+    fn returning_with_an_empty_stack_underflows() {
+        let mut cpu = cpu(&[0x6001, 0x00EE]);
+        run(&mut cpu, &mut NullKeypad, 1);
+        assert!(matches!(
+            cpu.tick(&mut NullKeypad),
+            Err(CpuError::StackUnderflow { pc: 0x202 })
+        ));
+    }
 }
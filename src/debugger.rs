@@ -0,0 +1,574 @@
+use std::collections::BTreeSet;
+
+use crate::cpu::{Access, MemoryAccess};
+use crate::{Cpu, Instruction};
+
+const HELP: &str = "\
+s, step            run one instruction
+n, next            run one instruction, stepping over subroutine calls
+f, finish          run until the current subroutine returns
+c, continue        run until a breakpoint or pause
+b, break [ADDR]    set a breakpoint at ADDR, or list breakpoints
+w, watch [ADDR [LEN]]
+                   pause after an instruction writes any of LEN bytes from
+                   ADDR (default 1), or list watchpoints
+rw, rwatch ADDR [LEN]
+                   pause after an instruction reads them as data
+aw, awatch ADDR [LEN]
+                   pause after an instruction reads or writes them
+d, delete ADDR     remove the breakpoint and watchpoints at ADDR
+r, regs            print registers, timers and stack
+bt, backtrace      print the calls on the stack, innermost first
+x, mem ADDR [LEN]  print LEN bytes of memory from ADDR (default 16)
+q, quit            exit the emulator
+h, help            print this message
+
+Addresses and lengths are hex, with or without a 0x prefix.";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    Running,
+    Paused,
+    Step,
+    /// Run until the stack is back down to this depth.
+    StepOver(usize),
+    /// Run until the stack is below this depth.
+    Finish(usize),
+}
+
+/// Which memory accesses a [`Watchpoint`] stops on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    Any,
+}
+
+/// Pauses execution after an instruction touches memory from `start` to
+/// `end` inclusive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub watch: Watch,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        let kind = match self.watch {
+            Watch::Read => access.kind == Access::Read,
+            Watch::Write => access.kind == Access::Write,
+            Watch::Any => true,
+        };
+        kind && access.overlaps(self.start, self.end)
+    }
+}
+
+/// What the frontend should do after a debugger command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Stay paused, showing this output to the user.
+    Stay(String),
+    /// Carry on executing.
+    Resume,
+    /// Exit the emulator.
+    Quit,
+}
+
+/// Step debugger with PC breakpoints and memory watchpoints, driven by text
+/// commands.
+///
+/// The frontend calls [`should_pause`](Debugger::should_pause) before every
+/// [`Cpu::tick`] and, while paused, feeds user input to
+/// [`command`](Debugger::command) instead of ticking. Watchpoints are
+/// checked against the accesses of the tick that has just run, so execution
+/// stops on the instruction after the one that touched memory.
+#[derive(Clone, Debug)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    // Why the last watchpoint stopped execution, until the frontend takes it
+    hit: Option<String>,
+    mode: Mode,
+    // The instruction the debugger stopped on is always allowed to run when
+    // resuming, so that continuing from a breakpoint doesn't hit it again
+    resuming: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            hit: None,
+            mode: Mode::Running,
+            resuming: false,
+        }
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Takes the description of the watchpoint that last stopped execution.
+    pub fn take_hit(&mut self) -> Option<String> {
+        self.hit.take()
+    }
+
+    /// Decides whether to stop before the instruction at `cpu.pc()`.
+    pub fn should_pause(&mut self, cpu: &Cpu) -> bool {
+        if std::mem::take(&mut self.resuming) {
+            if self.mode == Mode::Step {
+                self.mode = Mode::Paused;
+            }
+            return false;
+        }
+        // Checked even when already paused, so that stepping over an
+        // instruction that touches a watched address still reports it
+        self.hit = self.watch_hit(cpu);
+        if self.mode == Mode::Paused {
+            return true;
+        }
+        let depth = cpu.stack().len();
+        let pause = match self.mode {
+            Mode::Paused | Mode::Step => true,
+            Mode::Running => false,
+            Mode::StepOver(target) => depth <= target,
+            Mode::Finish(target) => depth < target,
+        } || self.breakpoints.contains(&cpu.pc())
+            || self.hit.is_some();
+        if pause {
+            self.mode = Mode::Paused;
+        }
+        pause
+    }
+
+    /// Runs a command typed at the debugger prompt.
+    pub fn command(&mut self, line: &str, cpu: &Cpu) -> Outcome {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Outcome::Stay(String::new());
+        };
+        let args: Vec<&str> = words.collect();
+        match (command, args.as_slice()) {
+            ("s" | "step", []) => self.resume(Mode::Step),
+            ("n" | "next", []) => {
+                // 2NNN is a subroutine call, anything else is a plain step
+                if cpu.current_instruction() & 0xF000 == 0x2000 {
+                    self.resume(Mode::StepOver(cpu.stack().len()))
+                } else {
+                    self.resume(Mode::Step)
+                }
+            }
+            ("f" | "finish", []) => {
+                if cpu.stack().is_empty() {
+                    return Outcome::Stay("not in a subroutine".to_string());
+                }
+                self.resume(Mode::Finish(cpu.stack().len()))
+            }
+            ("c" | "continue", []) => self.resume(Mode::Running),
+            ("b" | "break", []) => Outcome::Stay(self.format_breakpoints()),
+            ("b" | "break", [address]) => match parse_hex(address) {
+                Some(address) => {
+                    self.breakpoints.insert(address);
+                    Outcome::Stay(format!("breakpoint at {:#06x}", address))
+                }
+                None => Outcome::Stay(format!("invalid address {}", address)),
+            },
+            ("w" | "watch", []) => Outcome::Stay(self.format_watchpoints()),
+            ("w" | "watch", range) => self.watch(Watch::Write, range),
+            ("rw" | "rwatch", range) => self.watch(Watch::Read, range),
+            ("aw" | "awatch", range) => self.watch(Watch::Any, range),
+            ("d" | "delete", [address]) => match parse_hex(address) {
+                Some(address) => {
+                    let breakpoint = self.breakpoints.remove(&address);
+                    let watchpoints = self.watchpoints.len();
+                    self.watchpoints
+                        .retain(|watchpoint| watchpoint.start != address);
+                    if breakpoint || watchpoints != self.watchpoints.len() {
+                        Outcome::Stay(format!("removed breakpoints at {:#06x}", address))
+                    } else {
+                        Outcome::Stay(format!("no breakpoint at {:#06x}", address))
+                    }
+                }
+                None => Outcome::Stay(format!("invalid address {}", address)),
+            },
+            ("r" | "regs", []) => Outcome::Stay(format_state(cpu)),
+            ("bt" | "backtrace", []) => Outcome::Stay(format_backtrace(cpu)),
+            ("x" | "mem", [address, rest @ ..]) if rest.len() <= 1 => {
+                let length = rest.first().map_or(Some(16), |length| parse_hex(length));
+                match (parse_hex(address), length) {
+                    (Some(address), Some(length)) => {
+                        Outcome::Stay(format_memory(cpu, address, length))
+                    }
+                    _ => Outcome::Stay(format!("invalid memory range {}", args.join(" "))),
+                }
+            }
+            ("q" | "quit", []) => Outcome::Quit,
+            ("h" | "help", []) => Outcome::Stay(HELP.to_string()),
+            _ => Outcome::Stay(format!("unknown command {}, try help", line.trim())),
+        }
+    }
+
+    fn resume(&mut self, mode: Mode) -> Outcome {
+        self.mode = mode;
+        self.resuming = true;
+        Outcome::Resume
+    }
+
+    fn watch(&mut self, watch: Watch, range: &[&str]) -> Outcome {
+        let (address, length) = match range {
+            [address] => (parse_hex(address), Some(1)),
+            [address, length] => (parse_hex(address), parse_hex(length)),
+            _ => return Outcome::Stay("expected ADDR [LEN]".to_string()),
+        };
+        match (address, length) {
+            (Some(start), Some(length)) if length > 0 => {
+                let watchpoint = Watchpoint {
+                    start,
+                    end: start.saturating_add(length - 1),
+                    watch,
+                };
+                self.watchpoints.push(watchpoint);
+                Outcome::Stay(format!("watchpoint {}", format_watchpoint(&watchpoint)))
+            }
+            _ => Outcome::Stay(format!("invalid memory range {}", range.join(" "))),
+        }
+    }
+
+    /// Describes the first access of the last tick that a watchpoint covers.
+    fn watch_hit(&self, cpu: &Cpu) -> Option<String> {
+        cpu.memory_accesses().iter().find_map(|access| {
+            let watchpoint = self
+                .watchpoints
+                .iter()
+                .find(|watchpoint| watchpoint.matches(access))?;
+            let memory = cpu.memory();
+            let pc = usize::from(access.pc);
+            let word = memory[pc] << 8 | memory[(pc + 1) % memory.len()];
+            Some(format!(
+                "watchpoint {}: {} {:#06x}-{:#06x} by {:#06x}: {:04x}  {}",
+                format_watchpoint(watchpoint),
+                match access.kind {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                },
+                access.address,
+                u32::from(access.address) + u32::from(access.len) - 1,
+                access.pc,
+                word,
+                Instruction::decode(word),
+            ))
+        })
+    }
+
+    fn format_watchpoints(&self) -> String {
+        if self.watchpoints.is_empty() {
+            return "no watchpoints".to_string();
+        }
+        self.watchpoints
+            .iter()
+            .map(format_watchpoint)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn format_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "no breakpoints".to_string();
+        }
+        self.breakpoints()
+            .map(|address| format!("{:#06x}", address))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Registers, timers, stack and the next instruction, as shown when the
+/// debugger stops.
+pub fn format_state(cpu: &Cpu) -> String {
+    let registers = |range: std::ops::Range<usize>| {
+        cpu.registers()[range]
+            .iter()
+            .map(|v| format!("{:02x}", v))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let stack = cpu
+        .stack()
+        .iter()
+        .map(|address| format!("{:#06x}", address))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "pc {:#06x}  i {:#06x}  dt {}  st {}{}\n\
+         v0-v7 {}\n\
+         v8-vf {}\n\
+         stack [{}] {}/{}\n\
+         {:#06x}: {:04x}  {}",
+        cpu.pc(),
+        cpu.index(),
+        cpu.delay_timer(),
+        cpu.sound_timer(),
+        if cpu.awaiting_key() {
+            "  (waiting for key)"
+        } else {
+            ""
+        },
+        registers(0..8),
+        registers(8..16),
+        stack,
+        cpu.stack().len(),
+        cpu.stack_depth(),
+        cpu.pc(),
+        cpu.current_instruction(),
+        Instruction::decode(cpu.current_instruction()),
+    )
+}
+
+/// The call behind each return address on the stack, innermost first.
+fn format_backtrace(cpu: &Cpu) -> String {
+    if cpu.stack().is_empty() {
+        return "not in a subroutine".to_string();
+    }
+    cpu.stack()
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(level, &address)| {
+            let call = address.wrapping_sub(2);
+            let memory = cpu.memory();
+            let byte = |at: u16| memory.get(usize::from(at)).map_or(0, |&byte| byte as u8);
+            let instruction = u16::from_be_bytes([byte(call), byte(call.wrapping_add(1))]);
+            format!(
+                "#{:<2} {:#06x}: {:04x}  {}",
+                level,
+                call,
+                instruction,
+                Instruction::decode(instruction)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_watchpoint(watchpoint: &Watchpoint) -> String {
+    let watch = match watchpoint.watch {
+        Watch::Read => "read",
+        Watch::Write => "write",
+        Watch::Any => "access",
+    };
+    if watchpoint.start == watchpoint.end {
+        format!("{} {:#06x}", watch, watchpoint.start)
+    } else {
+        format!(
+            "{} {:#06x}-{:#06x}",
+            watch, watchpoint.start, watchpoint.end
+        )
+    }
+}
+
+fn format_memory(cpu: &Cpu, address: u16, length: u16) -> String {
+    let memory = cpu.memory();
+    let start = usize::from(address).min(memory.len());
+    let end = (start + usize::from(length)).min(memory.len());
+    memory[start..end]
+        .chunks(16)
+        .enumerate()
+        .map(|(line, bytes)| {
+            let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{:#06x}: {}", start + line * 16, bytes.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn parse_hex(value: &str) -> Option<u16> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::NullKeypad;
+
+    // 0x200: v0 = 1, i = 0x300, save v0, call 0x210, load v0, then loop
+    // 0x210: v0 += 1, return
+    const ROM: [u16; 10] = [
+        0x6001, 0xA300, 0xF055, 0x2210, 0xF065, 0x120A, 0x0000, 0x0000, 0x7001, 0x00EE,
+    ];
+
+    fn cpu() -> Cpu {
+        let bytes: Vec<u8> = ROM.iter().flat_map(|word| word.to_be_bytes()).collect();
+        Cpu::new().load_rom(&bytes).unwrap()
+    }
+
+    /// Ticks until the debugger pauses, returning where it stopped.
+    fn run(debugger: &mut Debugger, cpu: &mut Cpu) -> u16 {
+        for _ in 0..100 {
+            if debugger.should_pause(cpu) {
+                return cpu.pc();
+            }
+            cpu.tick(&mut NullKeypad).unwrap();
+        }
+        panic!("the debugger never paused");
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        debugger.command("b 204", &cpu);
+        debugger.command("break 0x208", &cpu);
+        assert_eq!(
+            debugger.command("b", &cpu),
+            Outcome::Stay("0x0204\n0x0208".to_string())
+        );
+
+        assert_eq!(run(&mut debugger, &mut cpu), 0x204);
+        assert_eq!(debugger.command("c", &cpu), Outcome::Resume);
+        // Continuing runs the instruction it stopped on instead of stopping
+        // on it again
+        assert_eq!(run(&mut debugger, &mut cpu), 0x208);
+
+        debugger.command("d 208", &cpu);
+        debugger.command("b 20a", &cpu);
+        debugger.command("c", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x20A);
+    }
+
+    #[test]
+    fn steps_one_instruction_at_a_time() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        assert_eq!(run(&mut debugger, &mut cpu), 0x200);
+        for pc in [0x202, 0x204, 0x206, 0x210, 0x212, 0x208] {
+            assert_eq!(debugger.command("s", &cpu), Outcome::Resume);
+            assert_eq!(run(&mut debugger, &mut cpu), pc);
+        }
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        debugger.command("b 206", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x206);
+
+        debugger.command("n", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x208);
+        assert_eq!(cpu.registers()[0], 2);
+        // Anything but a call is a plain step
+        debugger.command("next", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x20A);
+    }
+
+    #[test]
+    fn finish_runs_until_the_subroutine_returns() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        run(&mut debugger, &mut cpu);
+        assert_eq!(
+            debugger.command("f", &cpu),
+            Outcome::Stay("not in a subroutine".to_string())
+        );
+
+        debugger.command("b 210", &cpu);
+        debugger.command("c", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x210);
+        debugger.command("finish", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x208);
+        assert!(cpu.stack().is_empty());
+    }
+
+    #[test]
+    fn write_watchpoints_stop_after_the_write() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            debugger.command("w 300", &cpu),
+            Outcome::Stay("watchpoint write 0x0300".to_string())
+        );
+        assert_eq!(run(&mut debugger, &mut cpu), 0x206);
+        assert_eq!(
+            debugger.take_hit().unwrap(),
+            "watchpoint write 0x0300: write 0x0300-0x0300 by 0x0204: f055  save v0"
+        );
+        // The load only reads it
+        debugger.command("b 20a", &cpu);
+        debugger.command("c", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x20A);
+        assert_eq!(debugger.take_hit(), None);
+    }
+
+    #[test]
+    fn read_watchpoints_cover_a_range() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            debugger.command("rw 2fe 3", &cpu),
+            Outcome::Stay("watchpoint read 0x02fe-0x0300".to_string())
+        );
+        assert_eq!(run(&mut debugger, &mut cpu), 0x20A);
+        assert_eq!(
+            debugger.take_hit().unwrap(),
+            "watchpoint read 0x02fe-0x0300: read 0x0300-0x0300 by 0x0208: f065  load v0"
+        );
+    }
+
+    #[test]
+    fn access_watchpoints_stop_on_reads_and_writes() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        debugger.command("aw 300", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x206);
+        debugger.command("c", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x20A);
+    }
+
+    #[test]
+    fn watchpoints_ignore_accesses_outside_their_range() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        debugger.command("aw 301 4", &cpu);
+        debugger.command("b 20a", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x20A);
+        assert_eq!(debugger.take_hit(), None);
+    }
+
+    #[test]
+    fn stepping_over_a_watched_write_reports_it() {
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        debugger.command("w 300", &cpu);
+        debugger.command("b 204", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x204);
+
+        debugger.command("s", &cpu);
+        assert_eq!(run(&mut debugger, &mut cpu), 0x206);
+        assert!(debugger
+            .take_hit()
+            .is_some_and(|hit| hit.starts_with("watchpoint write 0x0300")));
+    }
+}
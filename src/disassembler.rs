@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::instruction::Instruction;
+
+/// Address programs are loaded at.
+pub const PROGRAM_START: u16 = 0x200;
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Label {
+    Jump,
+    Call,
+}
+
+/// Disassembles a ROM loaded at [`PROGRAM_START`].
+///
+/// Code is found by following every path of execution from the start
+/// address. Anything not reached that way (sprites, tables, or code only
+/// reached through `jump0`) is printed as data.
+pub fn disassemble(rom: &[u8]) -> String {
+    let code = find_code(rom);
+    let labels = find_labels(rom, &code);
+
+    let mut out = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = PROGRAM_START + offset as u16;
+        if let Some(label) = labels.get(&address) {
+            out.push_str(&format!(": {}\n", label_name(address, *label)));
+        }
+
+        if code.contains(&address) {
+            let word = read_word(rom, offset);
+            let instruction = Instruction::decode(word);
+            let text = match (instruction, instruction.target()) {
+                (Instruction::LongIndex, _) => {
+                    format!("i := long {:#06x}", read_word(rom, offset + 2))
+                }
+                (_, Some(target)) if labels.contains_key(&target) => {
+                    let name = label_name(target, labels[&target]);
+                    match instruction {
+                        Instruction::Call(_) => name,
+                        _ => format!("jump {}", name),
+                    }
+                }
+                _ => instruction.to_string(),
+            };
+            let raw = if instruction.size() == 4 {
+                format!("{:04x} {:04x}", word, read_word(rom, offset + 2))
+            } else {
+                format!("{:04x}     ", word)
+            };
+            out.push_str(&format!("{:#06x}  {}  {}\n", address, raw, text));
+            offset += usize::from(instruction.size());
+        } else {
+            // Data runs until the next piece of code or label
+            let end = (offset + 1..rom.len())
+                .find(|&end| {
+                    let address = PROGRAM_START + end as u16;
+                    code.contains(&address) || labels.contains_key(&address)
+                })
+                .unwrap_or(rom.len())
+                .min(offset + DATA_BYTES_PER_LINE);
+            let bytes: Vec<String> = rom[offset..end]
+                .iter()
+                .map(|byte| format!("{:#04x}", byte))
+                .collect();
+            out.push_str(&format!("{:#06x}  {}\n", address, bytes.join(" ")));
+            offset = end;
+        }
+    }
+    out
+}
+
+/// Addresses of every instruction reachable from the start address.
+fn find_code(rom: &[u8]) -> BTreeSet<u16> {
+    let end = PROGRAM_START as usize + rom.len();
+    let mut code = BTreeSet::new();
+    let mut pending = vec![PROGRAM_START];
+    while let Some(address) = pending.pop() {
+        // Instructions need all of their bytes to be inside the ROM
+        if address < PROGRAM_START || usize::from(address) + 1 >= end || code.contains(&address) {
+            continue;
+        }
+        let offset = usize::from(address - PROGRAM_START);
+        let instruction = Instruction::decode(read_word(rom, offset));
+        if usize::from(address) + usize::from(instruction.size()) > end {
+            continue;
+        }
+        code.insert(address);
+        let next = address.wrapping_add(instruction.size());
+        match instruction {
+            Instruction::Jump(target) => pending.push(target),
+            Instruction::Call(target) => pending.extend([target, next]),
+            Instruction::Return
+            | Instruction::Exit
+            | Instruction::JumpWithOffset(_)
+            | Instruction::Unknown(_) => (),
+            _ if instruction.is_skip() => {
+                // The skipped instruction may itself be a long index load
+                let size = match next.checked_sub(PROGRAM_START).map(usize::from) {
+                    Some(skipped) if skipped + 1 < rom.len() => {
+                        Instruction::decode(read_word(rom, skipped)).size()
+                    }
+                    _ => 2,
+                };
+                pending.extend([next, next.wrapping_add(size)]);
+            }
+            _ => pending.push(next),
+        }
+    }
+    code
+}
+
+fn find_labels(rom: &[u8], code: &BTreeSet<u16>) -> BTreeMap<u16, Label> {
+    // Targets outside the ROM have no line to put a label on
+    let end = PROGRAM_START as usize + rom.len();
+    let in_rom = |target: u16| target >= PROGRAM_START && usize::from(target) < end;
+    let mut labels = BTreeMap::new();
+    code.iter().for_each(|&address| {
+        let offset = usize::from(address - PROGRAM_START);
+        match Instruction::decode(read_word(rom, offset)) {
+            // A subroutine that's also jumped to is still named as one
+            Instruction::Call(target) if in_rom(target) => {
+                labels.insert(target, Label::Call);
+            }
+            Instruction::Jump(target) if in_rom(target) => {
+                labels.entry(target).or_insert(Label::Jump);
+            }
+            _ => (),
+        }
+    });
+    labels
+}
+
+fn label_name(address: u16, label: Label) -> String {
+    match label {
+        Label::Jump => format!("label_{:03x}", address),
+        Label::Call => format!("sub_{:03x}", address),
+    }
+}
+
+fn read_word(rom: &[u8], offset: usize) -> u16 {
+    let high = u16::from(rom[offset]);
+    let low = rom.get(offset + 1).copied().map_or(0, u16::from);
+    (high << 8) | low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_jumps_and_calls() {
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x00, 0xEE];
+        assert_eq!(
+            disassemble(&rom),
+            "0x0200  2204       sub_204\n\
+             : label_202\n\
+             0x0202  1202       jump label_202\n\
+             : sub_204\n\
+             0x0204  00ee       return\n"
+        );
+    }
+
+    #[test]
+    fn unreached_bytes_are_data() {
+        let rom = [0x12, 0x00, 0xF0, 0x90];
+        assert_eq!(
+            disassemble(&rom),
+            ": label_200\n0x0200  1200       jump label_200\n0x0202  0xf0 0x90\n"
+        );
+    }
+
+    #[test]
+    fn skips_targets_below_program_start() {
+        assert_eq!(
+            disassemble(&[0x10, 0x00]),
+            "0x0200  1000       jump 0x000\n"
+        );
+        assert_eq!(
+            disassemble(&[0x21, 0xFE]),
+            "0x0200  21fe       :call 0x1fe\n"
+        );
+    }
+
+    #[test]
+    fn long_index_at_the_end_is_data() {
+        let rom = [0x60, 0x01, 0xF0, 0x00];
+        assert_eq!(
+            disassemble(&rom),
+            "0x0200  6001       v0 := 0x01\n0x0202  0xf0 0x00\n"
+        );
+    }
+
+    #[test]
+    fn long_index_reads_the_following_word() {
+        let rom = [0xF0, 0x00, 0x12, 0x34];
+        assert_eq!(disassemble(&rom), "0x0200  f000 1234  i := long 0x1234\n");
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+/// How the screen reaches the window. Games erase a sprite, move it and
+/// draw it again, so showing the screen as it is can catch a sprite while
+/// it's gone; the modes other than `Normal` hide that flicker.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DisplayMode {
+    /// Show the screen at the end of every frame that changed it.
+    Normal,
+    /// Show the average of the last N frames.
+    Blend(usize),
+    /// Lit pixels fade out over a few frames, like phosphor.
+    Decay,
+    /// Only show frames that end with no draw still waiting for vblank.
+    /// When draws wait, a sprite is often erased in one frame and drawn
+    /// again in the next, so the frames in between are held back.
+    Vblank,
+}
+
+/// Frames blended when `--blend-frames` isn't given.
+pub const BLEND_FRAMES: usize = 3;
+
+/// How much of a pixel's brightness is left after each frame in decay mode.
+const DECAY: f32 = 0.6;
+
+impl DisplayMode {
+    pub const NAMES: [&'static str; 4] = ["normal", "blend", "decay", "vblank"];
+
+    /// Looks up a mode by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<DisplayMode> {
+        match name {
+            "normal" => Some(DisplayMode::Normal),
+            "blend" => Some(DisplayMode::Blend(BLEND_FRAMES)),
+            "decay" => Some(DisplayMode::Decay),
+            "vblank" => Some(DisplayMode::Vblank),
+            _ => None,
+        }
+    }
+
+    /// Whether the window changes from frame to frame even when the screen
+    /// doesn't.
+    pub fn animates(self) -> bool {
+        matches!(self, DisplayMode::Blend(_) | DisplayMode::Decay)
+    }
+}
+
+/// Turns each frame's colours into what the window shows, remembering as
+/// many past frames as the mode needs.
+pub struct Filter {
+    mode: DisplayMode,
+    /// Blend mode's recent frames, newest last.
+    history: VecDeque<Vec<u8>>,
+    /// Decay mode's last output, as floats so slow fades don't stall.
+    glow: Vec<f32>,
+}
+
+impl Filter {
+    pub fn new(mode: DisplayMode) -> Self {
+        Filter {
+            mode,
+            history: VecDeque::new(),
+            glow: Vec::new(),
+        }
+    }
+
+    /// Filters a frame of RGB bytes in place. `background` is the colour
+    /// pixels fade to.
+    pub fn apply(&mut self, pixels: &mut [u8], background: [u8; 3]) {
+        match self.mode {
+            DisplayMode::Normal | DisplayMode::Vblank => (),
+            DisplayMode::Blend(frames) => {
+                // A resolution change makes the old frames meaningless
+                if self
+                    .history
+                    .front()
+                    .is_some_and(|frame| frame.len() != pixels.len())
+                {
+                    self.history.clear();
+                }
+                if self.history.len() == frames {
+                    self.history.pop_front();
+                }
+                self.history.push_back(pixels.to_vec());
+                let count = self.history.len() as u32;
+                pixels.iter_mut().enumerate().for_each(|(at, byte)| {
+                    let sum: u32 = self.history.iter().map(|frame| u32::from(frame[at])).sum();
+                    *byte = (sum / count) as u8;
+                });
+            }
+            DisplayMode::Decay => {
+                if self.glow.len() != pixels.len() {
+                    self.glow = pixels.iter().map(|&byte| f32::from(byte)).collect();
+                }
+                pixels
+                    .iter_mut()
+                    .zip(self.glow.iter_mut())
+                    .enumerate()
+                    .for_each(|(at, (byte, glow))| {
+                        let background = f32::from(background[at % 3]);
+                        let target = f32::from(*byte);
+                        let faded = background + (*glow - background) * DECAY;
+                        // Pixels light up at once but only go out slowly
+                        if (target - background).abs() >= (faded - background).abs() {
+                            *glow = target;
+                        } else {
+                            *glow = faded;
+                        }
+                        *byte = glow.round() as u8;
+                    });
+            }
+        }
+    }
+}
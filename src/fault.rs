@@ -0,0 +1,114 @@
+use std::fmt;
+use std::io;
+
+/// Why the [`Cpu`](crate::Cpu) couldn't load a ROM or run an instruction.
+///
+/// Runtime faults carry the address of the instruction that caused them.
+#[derive(Debug)]
+pub enum CpuError {
+    Io(io::Error),
+    /// The ROM is bigger than the memory above 0x200.
+    RomTooLarge {
+        size: usize,
+        max: usize,
+    },
+    /// 00EE with nothing on the stack.
+    StackUnderflow {
+        pc: u16,
+    },
+    /// 2NNN with the stack already at its limit. `calls` holds the
+    /// addresses of the calls still on the stack, outermost first.
+    StackOverflow {
+        pc: u16,
+        calls: Vec<u16>,
+    },
+    /// A load or store would run past the end of memory.
+    MemoryOutOfRange {
+        pc: u16,
+        address: usize,
+    },
+    UnknownOpcode {
+        pc: u16,
+        opcode: u16,
+    },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::Io(error) => write!(f, "{}", error),
+            CpuError::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, but only {} fit in memory", size, max)
+            }
+            CpuError::StackUnderflow { pc } => {
+                write!(f, "{:#06x}: return with an empty stack", pc)
+            }
+            CpuError::StackOverflow { pc, calls } => {
+                write!(
+                    f,
+                    "{:#06x}: call with the stack full at {} levels",
+                    pc,
+                    calls.len()
+                )?;
+                if !calls.is_empty() {
+                    let chain: Vec<String> =
+                        calls.iter().map(|call| format!("{:#06x}", call)).collect();
+                    write!(f, ", called from {}", chain.join(" > "))?;
+                }
+                Ok(())
+            }
+            CpuError::MemoryOutOfRange { pc, address } => {
+                write!(
+                    f,
+                    "{:#06x}: memory access at {:#x} is out of range",
+                    pc, address
+                )
+            }
+            CpuError::UnknownOpcode { pc, opcode } => {
+                write!(f, "{:#06x}: unknown opcode {:04x}", pc, opcode)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CpuError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CpuError {
+    fn from(error: io::Error) -> Self {
+        CpuError::Io(error)
+    }
+}
+
+/// What [`Cpu::tick`](crate::Cpu::tick) does when an instruction faults.
+/// The faulting instruction is skipped in every case.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// Stop the CPU, as if it had run 00FD, and return the error.
+    #[default]
+    Halt,
+    /// Return the error but carry on running.
+    Warn,
+    /// Carry on running without reporting anything.
+    Ignore,
+}
+
+impl FaultPolicy {
+    pub const NAMES: [&'static str; 3] = ["halt", "warn", "ignore"];
+
+    /// Looks up a policy by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<FaultPolicy> {
+        match name {
+            "halt" => Some(FaultPolicy::Halt),
+            "warn" => Some(FaultPolicy::Warn),
+            "ignore" => Some(FaultPolicy::Ignore),
+            _ => None,
+        }
+    }
+}
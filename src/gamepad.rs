@@ -0,0 +1,57 @@
+use crate::config::ButtonMap;
+use anyhow::{Error, Result};
+use sdl2::controller::GameController;
+use sdl2::{GameControllerSubsystem, Sdl};
+
+/// The game controllers plugged in, and which of their buttons press each
+/// hex key. SDL reports controllers that are already connected as added
+/// when it starts, so everything is opened from
+/// [`Event::ControllerDeviceAdded`](sdl2::event::Event::ControllerDeviceAdded).
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+    button_map: ButtonMap,
+}
+
+impl Gamepads {
+    pub fn new(sdl_context: &Sdl, button_map: ButtonMap) -> Result<Self> {
+        let subsystem = sdl_context.game_controller().map_err(Error::msg)?;
+        Ok(Gamepads {
+            subsystem,
+            controllers: Vec::new(),
+            button_map,
+        })
+    }
+
+    /// Opens the controller at joystick index `which`.
+    pub fn add(&mut self, which: u32) {
+        match self.subsystem.open(which) {
+            Ok(controller) => {
+                let id = controller.instance_id();
+                if self.controllers.iter().all(|open| open.instance_id() != id) {
+                    println!("connected {}", controller.name());
+                    self.controllers.push(controller);
+                }
+            }
+            Err(error) => eprintln!("unable to open controller {}: {}", which, error),
+        }
+    }
+
+    /// Closes the controller with instance id `which`.
+    pub fn remove(&mut self, which: u32) {
+        self.controllers.retain(|controller| {
+            let keep = controller.instance_id() != which;
+            if !keep {
+                println!("disconnected {}", controller.name());
+            }
+            keep
+        });
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.controllers.iter().any(|controller| {
+            self.button_map
+                .is_pressed(key, |button| controller.button(button))
+        })
+    }
+}
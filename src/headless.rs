@@ -0,0 +1,163 @@
+use crate::args::Args;
+use crate::rom::{create_tracer, load_state, read_movie, read_rom};
+use crate::screenshot::{screenshot_path, write_png};
+use anyhow::{Context, Error, Result};
+use chip8_core::movie::Movie;
+use chip8_core::{Cpu, Random};
+
+/// How long a headless run lasts.
+#[derive(Copy, Clone, Debug)]
+pub enum RunLimit {
+    /// Number of 60hz frames, each spending the cycle budget of the timing
+    /// model.
+    Frames(u64),
+    /// Number of instructions.
+    Cycles(u64),
+}
+
+/// Frames run when neither `--frames` nor `--cycles` is given.
+const FRAMES: u64 = 60;
+
+/// Runs a ROM with no window, audio or input and prints the final machine
+/// state to stdout. A movie played back with `--play-movie` supplies the
+/// input, and the run lasts as long as the movie unless limited.
+pub fn run(args: Args) -> Result<()> {
+    if args.debug {
+        return Err(Error::msg(
+            "--debug needs a window and can't be used with --headless",
+        ));
+    }
+    if args.record_movie.is_some() {
+        return Err(Error::msg(
+            "--record-movie needs a window and can't be used with --headless",
+        ));
+    }
+    let rom = read_rom(&args.path)?;
+    let movie = args.play_movie.as_deref().map(read_movie).transpose()?;
+    let (mut cpu, cycles_per_frame) = match &movie {
+        Some(movie) => (movie.cpu(&rom)?, u64::from(movie.cycles_per_frame)),
+        None => (
+            Cpu::new()
+                .with_quirks(args.quirks)
+                .with_random(Random::xorshift(args.seed))
+                .with_timing(args.timing)
+                .load_rom(&rom)?,
+            args.cycles_per_frame(),
+        ),
+    };
+    cpu = cpu.with_fault_policy(args.on_fault);
+    if let Some(path) = &args.load_state {
+        load_state(&mut cpu, path.as_ref())?;
+    }
+    // With no movie the script is empty and no key is ever pressed
+    let mut keypad = movie.as_ref().map(Movie::keypad).unwrap_or_default();
+    let limit = args.limit.unwrap_or_else(|| {
+        let frames = match &movie {
+            Some(movie) => movie.len() as u64,
+            None => FRAMES,
+        };
+        // Run long enough to reach the screenshot
+        RunLimit::Frames(frames.max(args.screenshot_at.unwrap_or(0)))
+    });
+    let screenshot = |cpu: &Cpu, frame: u64| -> Result<()> {
+        let path = match &args.screenshot {
+            Some(path) => path.into(),
+            None => screenshot_path(&args.path, frame),
+        };
+        write_png(&path, &cpu.screen, &args.palette, args.scale)?;
+        eprintln!("saved screenshot to {}", path.display());
+        Ok(())
+    };
+    if args.screenshot_at == Some(0) {
+        screenshot(&cpu, 0)?;
+    }
+    let mut tracer = match &args.trace {
+        Some(path) => Some(create_tracer(
+            path,
+            args.trace_range.clone(),
+            args.trace_last,
+        )?),
+        None => None,
+    };
+    let mut instructions = 0;
+    let mut frames = 0;
+    let mut cycle = 0;
+    while !cpu.halted() {
+        match limit {
+            RunLimit::Frames(limit) if frames >= limit => break,
+            RunLimit::Cycles(limit) if instructions >= limit => break,
+            _ => (),
+        }
+        if let Some(tracer) = tracer.as_mut() {
+            tracer.before(&cpu);
+        }
+        let result = cpu.tick(&mut keypad);
+        if let Some(tracer) = tracer.as_mut() {
+            tracer.after(&cpu).context("unable to write trace")?;
+        }
+        if let Err(error) = result {
+            if cpu.halted() {
+                // The state at the fault is the useful part of the output
+                if let Some(tracer) = tracer.as_mut() {
+                    tracer.flush().context("unable to write trace")?;
+                }
+                print!("{}", dump(&cpu, instructions + 1));
+                return Err(Error::new(error).context("the ROM crashed"));
+            }
+            eprintln!("warning: {}", error);
+        }
+        instructions += 1;
+        cycle += u64::from(cpu.cycles());
+        if cycle >= (frames + 1) * cycles_per_frame {
+            cpu.tick_timers();
+            keypad.advance();
+            frames += 1;
+            if args.screenshot_at == Some(frames) {
+                screenshot(&cpu, frames)?;
+            }
+        }
+    }
+
+    print!("{}", dump(&cpu, instructions));
+    match args.screenshot_at {
+        Some(frame) if frame > frames => Err(Error::msg(format!(
+            "the run ended at frame {}, before the screenshot at frame {}",
+            frames, frame
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Indexed by pixel value, so XO-CHIP planes can be told apart
+const PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+fn dump(cpu: &Cpu, cycles: u64) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("cycles: {}\n", cycles));
+    out.push_str(&format!("pc: {:#06x}\n", cpu.pc()));
+    out.push_str(&format!("i: {:#06x}\n", cpu.index()));
+    let registers: Vec<String> = cpu
+        .registers()
+        .iter()
+        .map(|v| format!("{:02x}", v))
+        .collect();
+    out.push_str(&format!("v: {}\n", registers.join(" ")));
+    out.push_str(&format!("delay: {}\n", cpu.delay_timer()));
+    out.push_str(&format!("sound: {}\n", cpu.sound_timer()));
+    out.push_str(&format!("memory: {:016x}\n", fnv1a(cpu.memory())));
+    out.push_str("screen:\n");
+    cpu.screen.rows().for_each(|row| {
+        row.iter()
+            .for_each(|&pixel| out.push(PIXELS[usize::from(pixel & 0b11)]));
+        out.push('\n');
+    });
+    out
+}
+
+// FNV-1a is used rather than `DefaultHasher` so that the hash is stable
+// across Rust releases and can be checked into regression scripts
+fn fnv1a(memory: &[u16]) -> u64 {
+    memory.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte as u8)).wrapping_mul(0x100000001b3)
+    })
+}
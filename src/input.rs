@@ -0,0 +1,46 @@
+use crate::config::KeyMap;
+use crate::gamepad::Gamepads;
+use chip8_core::keypad::{KeyState, Keypad};
+use sdl2::EventPump;
+
+/// Keypad backed by the SDL keyboard and game controller state.
+pub struct SdlKeypad {
+    key_map: KeyMap,
+    state: KeyState,
+}
+
+impl SdlKeypad {
+    pub fn new(key_map: KeyMap) -> Self {
+        SdlKeypad {
+            key_map,
+            state: KeyState::default(),
+        }
+    }
+
+    /// Samples the keyboard and controllers. Call once per frame, after
+    /// pumping events.
+    pub fn update(&mut self, event_pump: &EventPump, gamepads: &Gamepads) {
+        let keyboard_state = event_pump.keyboard_state();
+        let mut pressed = [false; 16];
+        pressed.iter_mut().enumerate().for_each(|(key, pressed)| {
+            *pressed = self.key_map.is_pressed(key as u8, |scancode| {
+                keyboard_state.is_scancode_pressed(scancode)
+            }) || gamepads.is_pressed(key as u8);
+        });
+        self.state.update(pressed);
+    }
+
+    pub fn pressed(&self) -> [bool; 16] {
+        self.state.pressed()
+    }
+}
+
+impl Keypad for SdlKeypad {
+    fn is_pressed(&self, key: u8) -> bool {
+        self.state.is_pressed(key)
+    }
+
+    fn take_released(&mut self) -> Option<u8> {
+        self.state.take_released()
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+
+/// A decoded instruction word, independent of any machine state.
+///
+/// [`Display`](fmt::Display) renders Octo-style mnemonics. Addresses are
+/// printed as hex literals; the disassembler swaps in labels where it can.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    ClearScreen,
+    Return,
+    ScrollDown(u8),
+    ScrollUp(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    Jump(u16),
+    Call(u16),
+    SkipIfEqual(u8, u8),
+    SkipIfNotEqual(u8, u8),
+    SkipIfRegistersEqual(u8, u8),
+    SkipIfRegistersNotEqual(u8, u8),
+    StoreRange(u8, u8),
+    LoadRange(u8, u8),
+    SetRegister(u8, u8),
+    AddToRegister(u8, u8),
+    Copy(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    Add(u8, u8),
+    Subtract(u8, u8),
+    ShiftRight(u8, u8),
+    SubtractReversed(u8, u8),
+    ShiftLeft(u8, u8),
+    SetIndex(u16),
+    JumpWithOffset(u16),
+    Random(u8, u8),
+    Draw(u8, u8, u8),
+    SkipIfKey(u8),
+    SkipIfNotKey(u8),
+    GetDelayTimer(u8),
+    GetKey(u8),
+    SetDelayTimer(u8),
+    SetSoundTimer(u8),
+    AddToIndex(u8),
+    Font(u8),
+    BigFont(u8),
+    BinaryConversion(u8),
+    StoreMemory(u8),
+    LoadMemory(u8),
+    StoreFlags(u8),
+    LoadFlags(u8),
+    /// F000, the first word of the XO-CHIP long index load. The address is
+    /// the following word.
+    LongIndex,
+    SelectPlanes(u8),
+    LoadAudioPattern,
+    SetPitch(u8),
+    /// 0NNN, a call to COSMAC VIP machine code, which isn't supported.
+    MachineCode(u16),
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(word: u16) -> Instruction {
+        let kind = (word & 0xF000) >> 12;
+        let x = ((word & 0x0F00) >> 8) as u8;
+        let y = ((word & 0x00F0) >> 4) as u8;
+        let n = (word & 0x000F) as u8;
+        let nn = (word & 0x00FF) as u8;
+        let nnn = word & 0x0FFF;
+
+        match (kind, x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => Instruction::ClearScreen,
+            (0x0, 0x0, 0xE, 0xE) => Instruction::Return,
+            (0x0, 0x0, 0xC, n) => Instruction::ScrollDown(n),
+            (0x0, 0x0, 0xD, n) => Instruction::ScrollUp(n),
+            (0x0, 0x0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+            (0x0, 0x0, 0xF, 0xE) => Instruction::LowRes,
+            (0x0, 0x0, 0xF, 0xF) => Instruction::HighRes,
+            (0x0, _, _, _) => Instruction::MachineCode(nnn),
+            (0x1, _, _, _) => Instruction::Jump(nnn),
+            (0x2, _, _, _) => Instruction::Call(nnn),
+            (0x3, x, _, _) => Instruction::SkipIfEqual(x, nn),
+            (0x4, x, _, _) => Instruction::SkipIfNotEqual(x, nn),
+            (0x5, x, y, 0x0) => Instruction::SkipIfRegistersEqual(x, y),
+            (0x5, x, y, 0x2) => Instruction::StoreRange(x, y),
+            (0x5, x, y, 0x3) => Instruction::LoadRange(x, y),
+            (0x6, x, _, _) => Instruction::SetRegister(x, nn),
+            (0x7, x, _, _) => Instruction::AddToRegister(x, nn),
+            (0x8, x, y, 0x0) => Instruction::Copy(x, y),
+            (0x8, x, y, 0x1) => Instruction::Or(x, y),
+            (0x8, x, y, 0x2) => Instruction::And(x, y),
+            (0x8, x, y, 0x3) => Instruction::Xor(x, y),
+            (0x8, x, y, 0x4) => Instruction::Add(x, y),
+            (0x8, x, y, 0x5) => Instruction::Subtract(x, y),
+            (0x8, x, y, 0x6) => Instruction::ShiftRight(x, y),
+            (0x8, x, y, 0x7) => Instruction::SubtractReversed(x, y),
+            (0x8, x, y, 0xE) => Instruction::ShiftLeft(x, y),
+            (0x9, x, y, 0x0) => Instruction::SkipIfRegistersNotEqual(x, y),
+            (0xA, _, _, _) => Instruction::SetIndex(nnn),
+            (0xB, _, _, _) => Instruction::JumpWithOffset(nnn),
+            (0xC, x, _, _) => Instruction::Random(x, nn),
+            (0xD, x, y, n) => Instruction::Draw(x, y, n),
+            (0xE, x, 0x9, 0xE) => Instruction::SkipIfKey(x),
+            (0xE, x, 0xA, 0x1) => Instruction::SkipIfNotKey(x),
+            (0xF, 0x0, 0x0, 0x0) => Instruction::LongIndex,
+            (0xF, n, 0x0, 0x1) => Instruction::SelectPlanes(n),
+            (0xF, 0x0, 0x0, 0x2) => Instruction::LoadAudioPattern,
+            (0xF, x, 0x0, 0x7) => Instruction::GetDelayTimer(x),
+            (0xF, x, 0x0, 0xA) => Instruction::GetKey(x),
+            (0xF, x, 0x1, 0x5) => Instruction::SetDelayTimer(x),
+            (0xF, x, 0x1, 0x8) => Instruction::SetSoundTimer(x),
+            (0xF, x, 0x1, 0xE) => Instruction::AddToIndex(x),
+            (0xF, x, 0x2, 0x9) => Instruction::Font(x),
+            (0xF, x, 0x3, 0x0) => Instruction::BigFont(x),
+            (0xF, x, 0x3, 0x3) => Instruction::BinaryConversion(x),
+            (0xF, x, 0x3, 0xA) => Instruction::SetPitch(x),
+            (0xF, x, 0x5, 0x5) => Instruction::StoreMemory(x),
+            (0xF, x, 0x6, 0x5) => Instruction::LoadMemory(x),
+            (0xF, x, 0x7, 0x5) => Instruction::StoreFlags(x),
+            (0xF, x, 0x8, 0x5) => Instruction::LoadFlags(x),
+            _ => Instruction::Unknown(word),
+        }
+    }
+
+    /// Whether this instruction conditionally skips the one after it.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipIfEqual(..)
+                | Instruction::SkipIfNotEqual(..)
+                | Instruction::SkipIfRegistersEqual(..)
+                | Instruction::SkipIfRegistersNotEqual(..)
+                | Instruction::SkipIfKey(_)
+                | Instruction::SkipIfNotKey(_)
+        )
+    }
+
+    /// Size in bytes, including the address word of F000 NNNN.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LongIndex => 4,
+            _ => 2,
+        }
+    }
+
+    /// The address this instruction jumps to or calls, if fixed.
+    pub fn target(&self) -> Option<u16> {
+        match *self {
+            Instruction::Jump(address) | Instruction::Call(address) => Some(address),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Octo writes skips as the condition under which the next
+        // instruction runs, so each one reads as the opposite test
+        match *self {
+            Instruction::ClearScreen => write!(f, "clear"),
+            Instruction::Return => write!(f, "return"),
+            Instruction::ScrollDown(n) => write!(f, "scroll-down {}", n),
+            Instruction::ScrollUp(n) => write!(f, "scroll-up {}", n),
+            Instruction::ScrollRight => write!(f, "scroll-right"),
+            Instruction::ScrollLeft => write!(f, "scroll-left"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::LowRes => write!(f, "lores"),
+            Instruction::HighRes => write!(f, "hires"),
+            Instruction::Jump(nnn) => write!(f, "jump {:#05x}", nnn),
+            Instruction::Call(nnn) => write!(f, ":call {:#05x}", nnn),
+            Instruction::SkipIfEqual(x, nn) => write!(f, "if v{:x} != {:#04x} then", x, nn),
+            Instruction::SkipIfNotEqual(x, nn) => write!(f, "if v{:x} == {:#04x} then", x, nn),
+            Instruction::SkipIfRegistersEqual(x, y) => write!(f, "if v{:x} != v{:x} then", x, y),
+            Instruction::SkipIfRegistersNotEqual(x, y) => {
+                write!(f, "if v{:x} == v{:x} then", x, y)
+            }
+            Instruction::StoreRange(x, y) => write!(f, "save v{:x} - v{:x}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "load v{:x} - v{:x}", x, y),
+            Instruction::SetRegister(x, nn) => write!(f, "v{:x} := {:#04x}", x, nn),
+            Instruction::AddToRegister(x, nn) => write!(f, "v{:x} += {:#04x}", x, nn),
+            Instruction::Copy(x, y) => write!(f, "v{:x} := v{:x}", x, y),
+            Instruction::Or(x, y) => write!(f, "v{:x} |= v{:x}", x, y),
+            Instruction::And(x, y) => write!(f, "v{:x} &= v{:x}", x, y),
+            Instruction::Xor(x, y) => write!(f, "v{:x} ^= v{:x}", x, y),
+            Instruction::Add(x, y) => write!(f, "v{:x} += v{:x}", x, y),
+            Instruction::Subtract(x, y) => write!(f, "v{:x} -= v{:x}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "v{:x} >>= v{:x}", x, y),
+            Instruction::SubtractReversed(x, y) => write!(f, "v{:x} =- v{:x}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "v{:x} <<= v{:x}", x, y),
+            Instruction::SetIndex(nnn) => write!(f, "i := {:#05x}", nnn),
+            Instruction::JumpWithOffset(nnn) => write!(f, "jump0 {:#05x}", nnn),
+            Instruction::Random(x, nn) => write!(f, "v{:x} := random {:#04x}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "sprite v{:x} v{:x} {}", x, y, n),
+            Instruction::SkipIfKey(x) => write!(f, "if v{:x} -key then", x),
+            Instruction::SkipIfNotKey(x) => write!(f, "if v{:x} key then", x),
+            Instruction::GetDelayTimer(x) => write!(f, "v{:x} := delay", x),
+            Instruction::GetKey(x) => write!(f, "v{:x} := key", x),
+            Instruction::SetDelayTimer(x) => write!(f, "delay := v{:x}", x),
+            Instruction::SetSoundTimer(x) => write!(f, "buzzer := v{:x}", x),
+            Instruction::AddToIndex(x) => write!(f, "i += v{:x}", x),
+            Instruction::Font(x) => write!(f, "i := hex v{:x}", x),
+            Instruction::BigFont(x) => write!(f, "i := bighex v{:x}", x),
+            Instruction::BinaryConversion(x) => write!(f, "bcd v{:x}", x),
+            Instruction::StoreMemory(x) => write!(f, "save v{:x}", x),
+            Instruction::LoadMemory(x) => write!(f, "load v{:x}", x),
+            Instruction::StoreFlags(x) => write!(f, "saveflags v{:x}", x),
+            Instruction::LoadFlags(x) => write!(f, "loadflags v{:x}", x),
+            Instruction::LongIndex => write!(f, "i := long"),
+            Instruction::SelectPlanes(n) => write!(f, "plane {}", n),
+            Instruction::LoadAudioPattern => write!(f, "audio"),
+            Instruction::SetPitch(x) => write!(f, "pitch := v{:x}", x),
+            Instruction::MachineCode(nnn) => write!(f, "{:#04x} {:#04x}", nnn >> 8, nnn & 0xFF),
+            Instruction::Unknown(word) => write!(f, "{:#04x} {:#04x}", word >> 8, word & 0xFF),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(Instruction::decode(0x1234), Instruction::Jump(0x234));
+        assert_eq!(Instruction::decode(0x8AB5), Instruction::Subtract(0xA, 0xB));
+        assert_eq!(Instruction::decode(0xD12F), Instruction::Draw(1, 2, 0xF));
+        assert_eq!(Instruction::decode(0x5121), Instruction::Unknown(0x5121));
+    }
+
+    #[test]
+    fn long_index_is_four_bytes() {
+        assert_eq!(Instruction::decode(0xF000), Instruction::LongIndex);
+        assert_eq!(Instruction::LongIndex.size(), 4);
+        assert_eq!(Instruction::decode(0xF01E).size(), 2);
+    }
+
+    #[test]
+    fn skips_read_as_the_opposite_test() {
+        let skip = Instruction::decode(0x3105);
+        assert!(skip.is_skip());
+        assert_eq!(skip.to_string(), "if v1 != 0x05 then");
+    }
+}
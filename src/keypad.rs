@@ -0,0 +1,102 @@
+/// Source of hex keypad input for the [`Cpu`](crate::Cpu).
+///
+/// Keys are identified by their CHIP-8 value, `0x0` to `0xF`.
+pub trait Keypad {
+    /// Whether `key` is currently held down.
+    fn is_pressed(&self, key: u8) -> bool;
+
+    /// Takes the next key released since the keypad was last updated, if any.
+    fn take_released(&mut self) -> Option<u8>;
+}
+
+/// Pressed and released state for all 16 keys, shared by the keypad
+/// implementations that work by diffing successive snapshots.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct KeyState {
+    pressed: [bool; 16],
+    released: [bool; 16],
+}
+
+impl KeyState {
+    /// Replaces the pressed state, recording a release for every key that
+    /// was down before and is up now.
+    pub fn update(&mut self, pressed: [bool; 16]) {
+        self.released
+            .iter_mut()
+            .zip(self.pressed.iter().zip(pressed.iter()))
+            .for_each(|(released, (&was, &is))| *released = was && !is);
+        self.pressed = pressed;
+    }
+
+    pub fn pressed(&self) -> [bool; 16] {
+        self.pressed
+    }
+}
+
+impl Keypad for KeyState {
+    fn is_pressed(&self, key: u8) -> bool {
+        self.pressed[usize::from(key & 0xF)]
+    }
+
+    fn take_released(&mut self) -> Option<u8> {
+        let key = self.released.iter().position(|&released| released)?;
+        self.released[key] = false;
+        Some(key as u8)
+    }
+}
+
+/// A keypad with nothing ever pressed, for headless runs.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NullKeypad;
+
+impl Keypad for NullKeypad {
+    fn is_pressed(&self, _key: u8) -> bool {
+        false
+    }
+
+    fn take_released(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// A keypad that plays back a fixed sequence of frames, for tests.
+///
+/// Each call to [`advance`](ScriptedKeypad::advance) moves to the next frame;
+/// once the script runs out all keys are released.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedKeypad {
+    frames: Vec<[bool; 16]>,
+    frame: usize,
+    state: KeyState,
+}
+
+impl ScriptedKeypad {
+    pub fn new(frames: Vec<[bool; 16]>) -> Self {
+        let mut keypad = ScriptedKeypad {
+            frames,
+            frame: 0,
+            state: KeyState::default(),
+        };
+        keypad.state.update(keypad.current());
+        keypad
+    }
+
+    pub fn advance(&mut self) {
+        self.frame += 1;
+        self.state.update(self.current());
+    }
+
+    fn current(&self) -> [bool; 16] {
+        self.frames.get(self.frame).copied().unwrap_or_default()
+    }
+}
+
+impl Keypad for ScriptedKeypad {
+    fn is_pressed(&self, key: u8) -> bool {
+        self.state.is_pressed(key)
+    }
+
+    fn take_released(&mut self) -> Option<u8> {
+        self.state.take_released()
+    }
+}
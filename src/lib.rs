@@ -0,0 +1,30 @@
+//! Core CHIP-8 interpreter, free of any windowing or audio dependencies.
+//!
+//! The `chip-8` binary is a thin SDL frontend over this crate; other tools can
+//! embed [`Cpu`] directly and supply input through their own [`Keypad`].
+
+pub mod assembler;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod fault;
+pub mod instruction;
+pub mod keypad;
+pub mod movie;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod screen;
+pub mod state;
+pub mod timing;
+pub mod trace;
+
+pub use cpu::{Cpu, GRID_X_SIZE, GRID_Y_SIZE};
+pub use debugger::Debugger;
+pub use fault::{CpuError, FaultPolicy};
+pub use instruction::Instruction;
+pub use keypad::Keypad;
+pub use quirks::Quirks;
+pub use random::Random;
+pub use screen::Screen;
+pub use timing::Timing;
@@ -1,74 +1,62 @@
 mod args;
+#[cfg(feature = "sdl")]
 mod audio;
-mod cpu;
+#[cfg(feature = "sdl")]
+mod config;
+#[cfg(feature = "sdl")]
+mod console;
+#[cfg(feature = "sdl")]
+mod display;
+#[cfg(feature = "sdl")]
+mod gamepad;
+mod headless;
+#[cfg(feature = "sdl")]
+mod input;
+mod palette;
+#[cfg(feature = "sdl")]
 mod renderer;
+mod rom;
+mod screenshot;
+#[cfg(feature = "sdl")]
+mod window;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 
-use anyhow::{Error, Result};
-use args::parse_args;
-use audio::AudioPlayer;
-use cpu::Cpu;
-use renderer::Renderer;
-use sdl2::event::Event;
-use sdl2::keyboard::Scancode;
-use std::sync::{Arc, Mutex};
-use std::thread::{self, sleep};
-use std::time::{Duration, SystemTime};
-
-const HZ: f64 = 3000.0;
+use anyhow::{Context, Result};
+use args::{parse_args, Command};
+use chip8_core::disassembler::disassemble;
+use rom::read_rom;
+use std::path::Path;
 
 fn main() -> Result<()> {
     let args = parse_args()?;
-    let hertz = args.hertz.unwrap_or(HZ);
-    let cpu = Mutex::new(Cpu::new().load(args.path));
-    let timer_arc = Arc::new(cpu);
-    let cpu_lock = Arc::clone(&timer_arc);
-
-    let sdl_context = sdl2::init().map_err(Error::msg)?;
-    let mut renderer = Renderer::new(&sdl_context).map_err(Error::msg)?;
-    let audio_player = AudioPlayer::new(&sdl_context).map_err(Error::msg)?;
-
-    let mut event_pump = renderer.event_pump();
-
-    // Run timers in a 60hz cycle
-    thread::spawn(move || -> Result<()> {
-        loop {
-            let start = SystemTime::now();
-            let mut guard = timer_arc.lock().unwrap();
-            guard.tick_timers();
-            drop(guard);
-            sleep(Duration::from_secs_f64(1.0 / 60.0).saturating_sub(start.elapsed()?));
+    match args.command {
+        Command::Run => (),
+        Command::Disassemble => {
+            print!("{}", disassemble(&read_rom(&args.path)?));
+            return Ok(());
         }
-    });
-
-    'running: loop {
-        let start = SystemTime::now();
-        for event in event_pump.poll_iter() {
-            if let Event::KeyDown {
-                scancode: Some(Scancode::Escape),
-                ..
-            } = event
-            {
-                break 'running;
-            }
-        }
-
-        let mut guard = cpu_lock.lock().unwrap();
-
-        guard.tick(&event_pump);
-
-        if guard.should_draw() {
-            renderer.draw_screen(guard.screen);
+        Command::Assemble(output) => {
+            let output = output.unwrap_or_else(|| {
+                Path::new(&args.path)
+                    .with_extension("ch8")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            let rom = read_rom(&args.path)?;
+            std::fs::write(&output, rom).with_context(|| format!("unable to write {}", output))?;
+            return Ok(());
         }
+    }
+    if args.headless {
+        return headless::run(args);
+    }
 
-        if guard.should_beep() {
-            audio_player.beep();
-        } else {
-            audio_player.stop_beep();
-        }
+    #[cfg(feature = "sdl")]
+    return window::run(args);
 
-        drop(guard);
-        sleep(Duration::from_secs_f64(1.0 / hertz).saturating_sub(start.elapsed()?));
-    }
-    Ok(())
+    #[cfg(not(feature = "sdl"))]
+    Err(anyhow::Error::msg(
+        "built without the sdl feature, only --headless runs are available",
+    ))
 }
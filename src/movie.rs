@@ -0,0 +1,262 @@
+use std::fmt;
+
+use crate::keypad::ScriptedKeypad;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::random::Random;
+use crate::timing::Timing;
+use crate::{Cpu, CpuError};
+
+const HEADER: &str = "chip-8 movie 1";
+
+/// A problem in a movie file, with the line it was found on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MovieError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+/// Keypad input recorded frame by frame, along with everything else needed
+/// to replay a run exactly: the seed, the quirks, the timing model and the
+/// cycle budget of a frame.
+///
+/// Movies are stored as text, one frame per line, so they can be diffed and
+/// checked in alongside golden output. Each frame is a hex mask with bit `k`
+/// set while key `k` is held, optionally followed by a repeat count.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub seed: u32,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    pub cycles_per_frame: u32,
+    pub frames: Vec<[bool; 16]>,
+}
+
+impl Movie {
+    pub fn new(seed: u32, quirks: Quirks, timing: Timing, cycles_per_frame: u32) -> Self {
+        Movie {
+            seed,
+            quirks,
+            timing,
+            cycles_per_frame,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Appends the keys held during the next frame.
+    pub fn record(&mut self, pressed: [bool; 16]) {
+        self.frames.push(pressed);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// A CPU set up the way the recording started, with `rom` loaded.
+    pub fn cpu(&self, rom: &[u8]) -> Result<Cpu, CpuError> {
+        Cpu::new()
+            .with_quirks(self.quirks)
+            .with_random(Random::xorshift(self.seed))
+            .with_timing(self.timing)
+            .load_rom(rom)
+    }
+
+    /// A keypad playing back the recorded frames, starting at the first.
+    /// Advance it at the start of every following frame.
+    pub fn keypad(&self) -> ScriptedKeypad {
+        ScriptedKeypad::new(self.frames.clone())
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
+        match lines.next() {
+            Some((_, HEADER)) => (),
+            Some((line, _)) => return Err(error(line, "not a chip-8 movie")),
+            None => return Err(error(1, "not a chip-8 movie")),
+        }
+
+        let mut seed = None;
+        let mut cycles_per_frame = None;
+        let mut quirks = Quirks::default();
+        let mut timing = Timing::default();
+        let mut in_frames = false;
+        let mut frames = Vec::new();
+        for (line, text) in lines {
+            if in_frames {
+                let mut words = text.split_whitespace();
+                let mask = words
+                    .next()
+                    .and_then(|word| u16::from_str_radix(word, 16).ok())
+                    .ok_or_else(|| error(line, "expected a hex key mask"))?;
+                let count = match words.next() {
+                    Some(word) => word
+                        .parse()
+                        .map_err(|_| error(line, "expected a repeat count"))?,
+                    None => 1,
+                };
+                let mut pressed = [false; 16];
+                pressed
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(key, pressed)| *pressed = mask & (1 << key) != 0);
+                frames.extend(std::iter::repeat_n(pressed, count));
+                continue;
+            }
+
+            let (key, value) = match text.split_once(' ') {
+                Some((key, value)) => (key, value.trim()),
+                None if text == "frames" => {
+                    in_frames = true;
+                    continue;
+                }
+                None => return Err(error(line, format!("{} needs a value", text))),
+            };
+            match key {
+                "seed" => seed = Some(number(line, value)?),
+                "timing" => {
+                    timing = Timing::from_name(value)
+                        .ok_or_else(|| error(line, format!("unknown timing {}", value)))?
+                }
+                "cycles-per-frame" => cycles_per_frame = Some(number(line, value)?),
+                "vf-reset" => quirks.vf_reset = flag(line, value)?,
+                "memory-increment" => {
+                    quirks.memory_increment = match value {
+                        "none" => MemoryIncrement::None,
+                        "x" => MemoryIncrement::X,
+                        "x+1" => MemoryIncrement::XPlusOne,
+                        _ => return Err(error(line, "expected none, x or x+1")),
+                    }
+                }
+                "display-wait" => quirks.display_wait = flag(line, value)?,
+                "clipping" => quirks.clipping = flag(line, value)?,
+                "shifting" => quirks.shifting = flag(line, value)?,
+                "jumping" => quirks.jumping = flag(line, value)?,
+                "stack-depth" => {
+                    quirks.stack_depth = Some(number(line, value)? as usize)
+                        .filter(|depth| (1..=Quirks::MAX_STACK_DEPTH).contains(depth))
+                        .ok_or_else(|| {
+                            let max = Quirks::MAX_STACK_DEPTH;
+                            error(line, format!("stack-depth must be between 1 and {}", max))
+                        })?
+                }
+                _ => return Err(error(line, format!("unknown setting {}", key))),
+            }
+        }
+
+        Ok(Movie {
+            seed: seed.ok_or_else(|| error(1, "missing seed"))?,
+            quirks,
+            timing,
+            cycles_per_frame: cycles_per_frame
+                .filter(|&cycles| cycles > 0)
+                .ok_or_else(|| error(1, "missing cycles-per-frame"))?,
+            frames,
+        })
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quirks = &self.quirks;
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "seed {}", self.seed)?;
+        let timing = match self.timing {
+            Timing::Flat => "flat",
+            Timing::CosmacVip => "vip",
+        };
+        writeln!(f, "timing {}", timing)?;
+        writeln!(f, "cycles-per-frame {}", self.cycles_per_frame)?;
+        writeln!(f, "vf-reset {}", quirks.vf_reset)?;
+        let memory_increment = match quirks.memory_increment {
+            MemoryIncrement::None => "none",
+            MemoryIncrement::X => "x",
+            MemoryIncrement::XPlusOne => "x+1",
+        };
+        writeln!(f, "memory-increment {}", memory_increment)?;
+        writeln!(f, "display-wait {}", quirks.display_wait)?;
+        writeln!(f, "clipping {}", quirks.clipping)?;
+        writeln!(f, "shifting {}", quirks.shifting)?;
+        writeln!(f, "jumping {}", quirks.jumping)?;
+        writeln!(f, "stack-depth {}", quirks.stack_depth)?;
+        writeln!(f, "frames")?;
+        // Players mostly hold keys for many frames at a time, so runs of the
+        // same state share a line
+        let mut frames = self.frames.iter().peekable();
+        while let Some(pressed) = frames.next() {
+            let mut count = 1;
+            while frames.next_if_eq(&pressed).is_some() {
+                count += 1;
+            }
+            let mask = pressed
+                .iter()
+                .enumerate()
+                .filter(|(_, &pressed)| pressed)
+                .fold(0u16, |mask, (key, _)| mask | 1 << key);
+            match count {
+                1 => writeln!(f, "{:04x}", mask)?,
+                _ => writeln!(f, "{:04x} {}", mask, count)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn error(line: usize, message: impl Into<String>) -> MovieError {
+    MovieError {
+        line,
+        message: message.into(),
+    }
+}
+
+fn number(line: usize, value: &str) -> Result<u32, MovieError> {
+    value
+        .parse()
+        .map_err(|_| error(line, format!("expected a number, found {}", value)))
+}
+
+fn flag(line: usize, value: &str) -> Result<bool, MovieError> {
+    value
+        .parse()
+        .map_err(|_| error(line, format!("expected true or false, found {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_text() {
+        let mut movie = Movie::new(7, Quirks::COSMAC_VIP, Timing::Flat, 11);
+        movie.record([false; 16]);
+        movie.record([false; 16]);
+        let mut pressed = [false; 16];
+        pressed[0xA] = true;
+        movie.record(pressed);
+        assert_eq!(Movie::parse(&movie.to_string()), Ok(movie));
+    }
+
+    #[test]
+    fn rejects_out_of_range_stack_depths() {
+        let movie = Movie::new(7, Quirks::default(), Timing::Flat, 11).to_string();
+        for depth in ["0", "256"] {
+            let text = movie.replace("stack-depth 16", &format!("stack-depth {}", depth));
+            let error = Movie::parse(&text).unwrap_err();
+            assert_eq!(error.message, "stack-depth must be between 1 and 255");
+        }
+    }
+}
@@ -0,0 +1,76 @@
+/// Colours for each pixel value: background, plane 1, plane 2, then both
+/// planes. Plain CHIP-8 and SUPER-CHIP only use the first two.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub name: String,
+    pub colours: [[u8; 3]; 4],
+}
+
+impl Palette {
+    pub const PRESETS: [&'static str; 5] = ["amber", "green", "lcd", "octo", "contrast"];
+
+    /// Looks up a preset by the name used on the command line.
+    pub fn preset(name: &str) -> Option<Palette> {
+        let colours = match name {
+            "amber" => [[134, 84, 3], [253, 195, 10], [203, 85, 0], [255, 240, 170]],
+            // Phosphor green on black
+            "green" => [[0, 0, 0], [51, 255, 102], [0, 136, 51], [204, 255, 204]],
+            // Shades of an early handheld's LCD
+            "lcd" => [[155, 188, 15], [15, 56, 15], [139, 172, 15], [48, 98, 48]],
+            "octo" => [[153, 102, 0], [255, 204, 0], [255, 102, 0], [102, 34, 0]],
+            "contrast" => [[0, 0, 0], [255, 255, 255], [255, 255, 0], [0, 255, 255]],
+            _ => return None,
+        };
+        Some(Palette {
+            name: name.to_string(),
+            colours,
+        })
+    }
+
+    /// Parses a preset name, or two or four comma separated hex colours
+    /// such as `000000,33ff66`. With two colours, plane 2 gets a blend of
+    /// them and both planes get the foreground.
+    pub fn parse(value: &str) -> Option<Palette> {
+        if let Some(palette) = Palette::preset(value) {
+            return Some(palette);
+        }
+        let colours = value
+            .split(',')
+            .map(parse_colour)
+            .collect::<Option<Vec<_>>>()?;
+        let colours = match *colours.as_slice() {
+            [background, foreground] => {
+                let blend = [0, 1, 2].map(|channel| {
+                    ((u16::from(background[channel]) + u16::from(foreground[channel])) / 2) as u8
+                });
+                [background, foreground, blend, foreground]
+            }
+            [background, plane_1, plane_2, both] => [background, plane_1, plane_2, both],
+            _ => return None,
+        };
+        Some(Palette {
+            name: "custom".to_string(),
+            colours,
+        })
+    }
+
+    /// The colour for a pixel value from the screen.
+    pub fn colour(&self, pixel: u8) -> [u8; 3] {
+        self.colours[usize::from(pixel & 0b11)]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::preset("amber").unwrap()
+    }
+}
+
+fn parse_colour(value: &str) -> Option<[u8; 3]> {
+    let digits = value.trim().trim_start_matches('#');
+    if digits.len() != 6 {
+        return None;
+    }
+    let channel = |at: usize| u8::from_str_radix(digits.get(at..at + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
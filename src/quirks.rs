@@ -0,0 +1,106 @@
+/// Behaviours that differ between CHIP-8 interpreters.
+///
+/// Named presets follow the platforms checked by the Timendus quirks test
+/// ROM. [`Quirks::default`] keeps this emulator's original behaviour, which
+/// doesn't exactly match any one platform.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// How far FX55 and FX65 move I.
+    pub memory_increment: MemoryIncrement,
+    /// DXYN waits for the next 60hz frame before continuing.
+    pub display_wait: bool,
+    /// Sprites are clipped at the screen edge rather than wrapping round.
+    pub clipping: bool,
+    /// 8XY6 and 8XYE shift VX in place, ignoring VY.
+    pub shifting: bool,
+    /// BXNN jumps to XNN + VX rather than NNN + V0.
+    pub jumping: bool,
+    /// How many calls can be nested before 2NNN overflows the stack.
+    pub stack_depth: usize,
+}
+
+/// What FX55 and FX65 do to I after storing or loading registers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemoryIncrement {
+    /// I is left untouched.
+    None,
+    /// I is increased by X, as on CHIP-48.
+    X,
+    /// I is increased by X + 1, as on the COSMAC VIP.
+    XPlusOne,
+}
+
+impl Quirks {
+    /// Deepest call stack a configuration may ask for, so that save states
+    /// can store the stack's length in a byte.
+    pub const MAX_STACK_DEPTH: usize = 255;
+
+    pub const COSMAC_VIP: Quirks = Quirks {
+        vf_reset: true,
+        memory_increment: MemoryIncrement::XPlusOne,
+        display_wait: true,
+        clipping: true,
+        shifting: false,
+        jumping: false,
+        stack_depth: 12,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::X,
+        display_wait: false,
+        clipping: true,
+        shifting: true,
+        jumping: true,
+        stack_depth: 16,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::None,
+        display_wait: false,
+        clipping: true,
+        shifting: true,
+        jumping: true,
+        stack_depth: 16,
+    };
+
+    pub const MODERN: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: MemoryIncrement::XPlusOne,
+        display_wait: false,
+        clipping: false,
+        shifting: false,
+        jumping: false,
+        stack_depth: 16,
+    };
+
+    pub const PRESETS: [&'static str; 4] = ["vip", "chip48", "schip", "modern"];
+
+    /// Looks up a preset by the name used on the command line.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" => Some(Quirks::CHIP_48),
+            "schip" => Some(Quirks::SUPER_CHIP),
+            "modern" => Some(Quirks::MODERN),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            vf_reset: false,
+            memory_increment: MemoryIncrement::None,
+            display_wait: false,
+            clipping: true,
+            shifting: true,
+            jumping: false,
+            stack_depth: 16,
+        }
+    }
+}
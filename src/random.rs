@@ -0,0 +1,63 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Where CXNN gets its random bytes from.
+///
+/// The generator is part of the machine state, so the same seed, ROM and
+/// input always produce the same run, and save states carry on the sequence
+/// where it left off. Other generators, such as an emulation of a specific
+/// interpreter's, slot in as further variants.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Random {
+    /// Marsaglia's 32-bit xorshift. The state is never zero.
+    Xorshift(u32),
+}
+
+impl Random {
+    /// A xorshift generator started from `seed`. Every seed, including 0,
+    /// gives a usable generator.
+    pub fn xorshift(seed: u32) -> Random {
+        // Mix the seed so that nearby seeds give unrelated sequences, and
+        // fold in a constant as xorshift gets stuck on 0
+        let mut state = seed ^ 0x9E37_79B9;
+        state = (state ^ (state >> 16)).wrapping_mul(0x85EB_CA6B);
+        state = (state ^ (state >> 13)).wrapping_mul(0xC2B2_AE35);
+        state ^= state >> 16;
+        Random::Xorshift(if state == 0 { 1 } else { state })
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        match self {
+            Random::Xorshift(state) => {
+                *state ^= *state << 13;
+                *state ^= *state >> 17;
+                *state ^= *state << 5;
+                (*state >> 24) as u8
+            }
+        }
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        match self {
+            Random::Xorshift(state) => {
+                writer.u8(0);
+                writer.u32(*state);
+            }
+        }
+    }
+
+    pub(crate) fn load_state(reader: &mut StateReader) -> Result<Random, StateError> {
+        match reader.u8()? {
+            0 => match reader.u32()? {
+                0 => Err(StateError::Invalid("random state")),
+                state => Ok(Random::Xorshift(state)),
+            },
+            _ => Err(StateError::Invalid("random generator")),
+        }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::xorshift(0)
+    }
+}
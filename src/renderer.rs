@@ -1,68 +1,150 @@
-use anyhow::{Error, Result};
-use sdl2::pixels::Color;
+use crate::args::SCALE;
+use crate::display::{DisplayMode, Filter};
+use crate::palette::Palette;
+use anyhow::{Context, Error, Result};
+use chip8_core::{Screen, GRID_X_SIZE, GRID_Y_SIZE};
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::WindowCanvas;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::{FullscreenType, WindowContext};
 use sdl2::{EventPump, Sdl};
 
-pub const GRID_X_SIZE: u32 = 64;
-pub const GRID_Y_SIZE: u32 = 32;
-pub const DOT_SIZE_IN_PXS: u32 = 10;
+/// Opens a resizable window `scale` times the size of the low-res screen.
+pub fn open_window(sdl_context: &Sdl, scale: u32) -> Result<WindowCanvas> {
+    let video_subsystem = sdl_context.video().map_err(Error::msg)?;
+    let window = video_subsystem
+        .window("chip-8", GRID_X_SIZE * scale, GRID_Y_SIZE * scale)
+        .position_centered()
+        .resizable()
+        .opengl()
+        .build()
+        .context("unable to create window")?;
+    Ok(window.into_canvas().build()?)
+}
 
 pub struct Renderer<'a> {
     canvas: WindowCanvas,
     sdl_context: &'a Sdl,
-    pub screen: [[u8; 64]; 32],
+    texture_creator: &'a TextureCreator<WindowContext>,
+    palette: Palette,
+    filter: Filter,
+    /// RGB bytes for the last screen drawn, after filtering, a row at a
+    /// time.
+    pixels: Vec<u8>,
+    width: usize,
+    height: usize,
+    /// Holds the pixels on the GPU, sized to the screen's resolution.
+    texture: Option<Texture<'a>>,
 }
 
-impl Renderer<'_> {
-    pub fn new(sdl_context: &Sdl) -> Result<Renderer> {
-        let video_subsystem = sdl_context.video().map_err(Error::msg)?;
-        let window = video_subsystem
-            .window(
-                "chip-8",
-                GRID_X_SIZE * DOT_SIZE_IN_PXS,
-                GRID_Y_SIZE * DOT_SIZE_IN_PXS,
-            )
-            .position_centered()
-            .opengl()
-            .build()
-            .expect("unable to create window");
-        let canvas = window.into_canvas().build()?;
-        Ok(Renderer {
+impl<'a> Renderer<'a> {
+    /// Draws to `canvas` with textures from `texture_creator`, which should
+    /// be the canvas's own.
+    pub fn new(
+        sdl_context: &'a Sdl,
+        canvas: WindowCanvas,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        palette: Palette,
+        mode: DisplayMode,
+    ) -> Renderer<'a> {
+        Renderer {
             canvas,
             sdl_context,
-            screen: [[0; GRID_X_SIZE as usize]; GRID_Y_SIZE as usize],
-        })
+            texture_creator,
+            palette,
+            filter: Filter::new(mode),
+            pixels: Vec::new(),
+            width: GRID_X_SIZE as usize,
+            height: GRID_Y_SIZE as usize,
+            texture: None,
+        }
+    }
+
+    /// Switches palette. Takes effect from the next draw.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
     }
 
     pub fn event_pump(&self) -> EventPump {
         self.sdl_context.event_pump().unwrap()
     }
 
-    fn draw_pixel(&mut self, x: u32, y: u32) {
-        self.canvas.set_draw_color(Color::RGB(253, 195, 10));
-        let _ = self.canvas.fill_rect(Rect::new(
-            (x * DOT_SIZE_IN_PXS) as i32,
-            (y * DOT_SIZE_IN_PXS) as i32,
-            DOT_SIZE_IN_PXS,
-            DOT_SIZE_IN_PXS,
-        ));
+    /// Switches between a window and desktop fullscreen.
+    pub fn toggle_fullscreen(&mut self) -> Result<()> {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(fullscreen).map_err(Error::msg)
+    }
+
+    pub fn draw_screen(&mut self, screen: &Screen) {
+        self.width = screen.width();
+        self.height = screen.height();
+        self.pixels.clear();
+        screen.rows().flatten().for_each(|&pixel| {
+            self.pixels.extend_from_slice(&self.palette.colour(pixel));
+        });
+        self.filter.apply(&mut self.pixels, self.palette.colour(0));
+        self.present();
     }
 
-    fn draw_background(&mut self) {
-        self.canvas.set_draw_color(Color::RGB(134, 84, 3));
-        self.canvas.clear()
+    /// Shows the last screen drawn again, after the window has changed size.
+    pub fn redraw(&mut self) {
+        self.present();
     }
 
-    pub fn draw_screen(&mut self, screen: [[u8; 64]; 32]) {
-        self.draw_background();
-        screen.iter().enumerate().for_each(|(y, row)| {
-            row.iter().enumerate().for_each(|(x, &pixel)| {
-                if pixel != 0 {
-                    self.draw_pixel(x.try_into().unwrap(), y.try_into().unwrap());
+    /// Uploads the pixels to the texture and stretches it over the largest
+    /// 2:1 area that fits the window, leaving black bars around it.
+    fn present(&mut self) {
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        if !self.pixels.is_empty() {
+            let viewport = self.viewport();
+            match self.upload() {
+                Ok(()) => {
+                    if let Some(texture) = &self.texture {
+                        let _ = self.canvas.copy(texture, None, viewport);
+                    }
                 }
-            })
-        });
+                Err(error) => eprintln!("unable to draw screen: {}", error),
+            }
+        }
         self.canvas.present();
     }
+
+    /// Copies the pixels into the texture, making a new one only when the
+    /// resolution has changed.
+    fn upload(&mut self) -> Result<(), String> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let texture = match self.texture.take() {
+            Some(texture) if texture.query().width == width && texture.query().height == height => {
+                texture
+            }
+            _ => self
+                .texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+                .map_err(|error| error.to_string())?,
+        };
+        self.texture
+            .insert(texture)
+            .update(None, &self.pixels, self.width * 3)
+            .map_err(|error| error.to_string())
+    }
+
+    fn viewport(&self) -> Rect {
+        let (window_width, window_height) = self
+            .canvas
+            .output_size()
+            .unwrap_or((GRID_X_SIZE * SCALE, GRID_Y_SIZE * SCALE));
+        let width = window_width.min(window_height * 2);
+        let height = width / 2;
+        Rect::new(
+            ((window_width - width) / 2) as i32,
+            ((window_height - height) / 2) as i32,
+            width.max(1),
+            height.max(1),
+        )
+    }
 }
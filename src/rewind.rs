@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+
+use crate::Cpu;
+
+/// 30 seconds at 60 frames per second.
+pub const DEFAULT_FRAMES: usize = 30 * 60;
+
+// Differences closer together than this are stored as one run, since each
+// run costs six bytes of header
+const MERGE_GAP: usize = 8;
+
+/// Ring buffer of per-frame snapshots for playing a game backwards.
+///
+/// Only the newest snapshot is kept whole. Each older frame is stored as
+/// the changes that turn the frame after it back into it, which for most
+/// frames is a handful of registers and screen bytes rather than the whole
+/// of memory.
+#[derive(Clone, Debug)]
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    history: VecDeque<Delta>,
+}
+
+#[derive(Clone, Debug)]
+struct Delta {
+    len: usize,
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Rewind::new(DEFAULT_FRAMES)
+    }
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Number of frames that can be stepped back through.
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    /// Size of the stored history in bytes, for keeping an eye on the
+    /// compression.
+    pub fn size(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len)
+            + self
+                .history
+                .iter()
+                .map(|delta| {
+                    delta
+                        .runs
+                        .iter()
+                        .map(|(_, run)| run.len() + 6)
+                        .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+
+    /// Snapshots the CPU as the newest frame. A state identical to the
+    /// newest, such as the one just restored by
+    /// [`step_back`](Rewind::step_back), isn't stored again, so it doesn't
+    /// take an extra step to rewind past.
+    pub fn record(&mut self, cpu: &Cpu) {
+        let state = cpu.save_state();
+        if self.latest.as_ref() == Some(&state) {
+            return;
+        }
+        if let Some(previous) = self.latest.take() {
+            self.history.push_back(Delta::between(&state, &previous));
+            if self.history.len() > self.capacity {
+                self.history.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Restores the CPU to the frame before the newest one, dropping the
+    /// newest. Returns false, leaving the CPU on the oldest frame, once the
+    /// history runs out.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> bool {
+        let Some(latest) = self.latest.as_mut() else {
+            return false;
+        };
+        let stepped = match self.history.pop_back() {
+            Some(delta) => {
+                *latest = delta.apply(latest);
+                true
+            }
+            None => false,
+        };
+        cpu.load_state(latest)
+            .expect("rewind snapshots are always valid states");
+        stepped
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.history.clear();
+    }
+}
+
+impl Delta {
+    /// The changes that turn `from` into `to`.
+    fn between(from: &[u8], to: &[u8]) -> Delta {
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        let differs = |offset: usize| from.get(offset) != Some(&to[offset]);
+        let mut offset = 0;
+        while offset < to.len() {
+            if !differs(offset) {
+                offset += 1;
+                continue;
+            }
+            let start = offset;
+            let mut end = offset + 1;
+            // Extend the run while the next difference is close enough
+            while let Some(next) = (end..(end + MERGE_GAP).min(to.len())).find(|&i| differs(i)) {
+                end = next + 1;
+            }
+            runs.push((start, to[start..end].to_vec()));
+            offset = end;
+        }
+        Delta {
+            len: to.len(),
+            runs,
+        }
+    }
+
+    fn apply(&self, from: &[u8]) -> Vec<u8> {
+        let mut to = from[..from.len().min(self.len)].to_vec();
+        to.resize(self.len, 0);
+        self.runs.iter().for_each(|(offset, run)| {
+            to[*offset..offset + run.len()].copy_from_slice(run);
+        });
+        to
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::ScriptedKeypad;
+
+    #[test]
+    fn deltas_restore_the_older_state() {
+        let newer = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let older = [1, 0, 3, 4, 5, 6, 7, 8, 9, 10, 11, 0];
+        assert_eq!(Delta::between(&newer, &older).apply(&newer), older);
+        // Changing resolution changes the length of a state
+        assert_eq!(
+            Delta::between(&newer, &older[..4]).apply(&newer),
+            older[..4]
+        );
+        assert_eq!(
+            Delta::between(&older[..4], &newer).apply(&older[..4]),
+            newer
+        );
+    }
+
+    #[test]
+    fn steps_back_through_recorded_frames() {
+        // Count key presses in v1
+        let rom: [u16; 3] = [0xF00A, 0x7101, 0x1200];
+        let bytes: Vec<u8> = rom.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let mut cpu = Cpu::new().load_rom(&bytes).unwrap();
+        let frames = (0..8).map(|frame| {
+            let mut keys = [false; 16];
+            keys[frame % 4] = frame % 2 == 0;
+            keys
+        });
+        let mut keypad = ScriptedKeypad::new(frames.collect());
+        let mut rewind = Rewind::default();
+        let mut states = Vec::new();
+        for _ in 0..8 {
+            (0..4).for_each(|_| cpu.tick(&mut keypad).unwrap());
+            keypad.advance();
+            rewind.record(&cpu);
+            states.push(cpu.save_state());
+        }
+        assert_eq!(rewind.len(), 7);
+
+        states.pop();
+        while let Some(state) = states.pop() {
+            assert!(rewind.step_back(&mut cpu));
+            assert_eq!(cpu.save_state(), state);
+        }
+        assert!(!rewind.step_back(&mut cpu));
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_past_capacity() {
+        let mut cpu = Cpu::new().load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut keypad = ScriptedKeypad::default();
+        let mut rewind = Rewind::new(3);
+        for _ in 0..10 {
+            cpu.tick(&mut keypad).unwrap();
+            rewind.record(&cpu);
+        }
+        assert_eq!(rewind.len(), 3);
+    }
+
+    #[test]
+    fn recording_after_a_rewind_does_not_duplicate_the_restored_frame() {
+        let mut cpu = Cpu::new().load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut keypad = ScriptedKeypad::default();
+        let mut rewind = Rewind::default();
+        for _ in 0..4 {
+            rewind.record(&cpu);
+            cpu.tick(&mut keypad).unwrap();
+        }
+        assert!(rewind.step_back(&mut cpu));
+        assert_eq!(rewind.len(), 2);
+
+        // The frame loop records again before running the restored frame
+        rewind.record(&cpu);
+        assert_eq!(rewind.len(), 2);
+        assert!(rewind.step_back(&mut cpu));
+        assert_eq!(cpu.registers()[0], 1);
+    }
+}
@@ -0,0 +1,52 @@
+use anyhow::{Context, Result};
+use chip8_core::assembler::assemble;
+use chip8_core::movie::Movie;
+use chip8_core::trace::Tracer;
+use chip8_core::Cpu;
+use std::fs::{read, read_to_string, File};
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Reads a ROM from disk, assembling it first if it's Octo source.
+pub fn read_rom(path: &str) -> Result<Vec<u8>> {
+    let bytes = read(path).with_context(|| format!("unable to read {}", path))?;
+    if !is_source(path) {
+        return Ok(bytes);
+    }
+    let source = String::from_utf8(bytes).with_context(|| format!("{} isn't UTF-8", path))?;
+    assemble(&source).with_context(|| format!("unable to assemble {}", path))
+}
+
+pub fn is_source(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension == "8o")
+}
+
+pub fn load_state(cpu: &mut Cpu, path: &Path) -> Result<()> {
+    let bytes = read(path).with_context(|| format!("unable to read {}", path.display()))?;
+    cpu.load_state(&bytes)
+        .with_context(|| format!("unable to load {}", path.display()))
+}
+
+pub fn read_movie(path: &str) -> Result<Movie> {
+    let text = read_to_string(path).with_context(|| format!("unable to read {}", path))?;
+    Movie::parse(&text).with_context(|| format!("unable to load movie {}", path))
+}
+
+pub fn create_tracer(
+    path: &str,
+    range: Option<RangeInclusive<u16>>,
+    last: Option<usize>,
+) -> Result<Tracer> {
+    let file = File::create(path).with_context(|| format!("unable to create {}", path))?;
+    let mut tracer = Tracer::new(BufWriter::new(file));
+    if let Some(range) = range {
+        tracer = tracer.with_range(range);
+    }
+    if let Some(last) = last {
+        tracer = tracer.with_ring(last);
+    }
+    Ok(tracer)
+}
@@ -0,0 +1,117 @@
+use crate::cpu::{GRID_X_SIZE, GRID_Y_SIZE};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// The display buffer, which switches between the 64x32 CHIP-8 resolution
+/// and the 128x64 SUPER-CHIP hi-res mode at runtime.
+///
+/// Each pixel holds a bitmask of the XO-CHIP planes lit at that position, so
+/// values range from 0 to 3 and index into a four colour palette.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Screen {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Screen::new(GRID_X_SIZE as usize, GRID_Y_SIZE as usize)
+    }
+}
+
+impl Screen {
+    fn new(width: usize, height: usize) -> Self {
+        Screen {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width > GRID_X_SIZE as usize
+    }
+
+    /// Switches resolution, clearing the screen.
+    pub fn set_hires(&mut self, hires: bool) {
+        let scale = if hires { 2 } else { 1 };
+        *self = Screen::new(GRID_X_SIZE as usize * scale, GRID_Y_SIZE as usize * scale);
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut u8 {
+        &mut self.pixels[y * self.width + x]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    /// Clears the given planes.
+    pub fn clear(&mut self, planes: u8) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel &= !planes);
+    }
+
+    pub fn scroll_down(&mut self, planes: u8, n: usize) {
+        self.scroll(planes, 0, n as isize);
+    }
+
+    pub fn scroll_up(&mut self, planes: u8, n: usize) {
+        self.scroll(planes, 0, -(n as isize));
+    }
+
+    pub fn scroll_right(&mut self, planes: u8, n: usize) {
+        self.scroll(planes, n as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, planes: u8, n: usize) {
+        self.scroll(planes, -(n as isize), 0);
+    }
+
+    /// Moves the given planes by `dx`, `dy`, filling the space left behind
+    /// with unlit pixels. Other planes stay where they are.
+    fn scroll(&mut self, planes: u8, dx: isize, dy: isize) {
+        let source = self.pixels.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (from_x, from_y) = (x as isize - dx, y as isize - dy);
+                let moved = if (0..self.width as isize).contains(&from_x)
+                    && (0..self.height as isize).contains(&from_y)
+                {
+                    source[from_y as usize * self.width + from_x as usize] & planes
+                } else {
+                    0
+                };
+                let pixel = self.pixel_mut(x, y);
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.is_hires());
+        writer.bytes(&self.pixels);
+    }
+
+    pub(crate) fn load_state(reader: &mut StateReader) -> Result<Screen, StateError> {
+        let mut screen = Screen::default();
+        screen.set_hires(reader.bool()?);
+        let pixels = reader.bytes()?;
+        if pixels.len() != screen.pixels.len() {
+            return Err(StateError::Invalid("screen"));
+        }
+        screen.pixels.copy_from_slice(pixels);
+        Ok(screen)
+    }
+}
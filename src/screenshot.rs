@@ -0,0 +1,44 @@
+use crate::palette::Palette;
+use anyhow::{Context, Result};
+use chip8_core::{Screen, GRID_X_SIZE};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Writes the screen to a PNG in the given palette, `scale` image pixels
+/// per low-res CHIP-8 pixel, the same size the window opens at. Hi-res
+/// screens come out the same size with pixels half as big.
+pub fn write_png(path: &Path, screen: &Screen, palette: &Palette, scale: u32) -> Result<()> {
+    let dot = (GRID_X_SIZE * scale / screen.width() as u32).max(1) as usize;
+    let width = screen.width() * dot;
+    let height = screen.height() * dot;
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    screen.rows().for_each(|row| {
+        let line: Vec<u8> = row
+            .iter()
+            .flat_map(|&pixel| std::iter::repeat_n(palette.colour(pixel), dot))
+            .flatten()
+            .collect();
+        (0..dot).for_each(|_| pixels.extend_from_slice(&line));
+    });
+
+    let file =
+        File::create(path).with_context(|| format!("unable to create {}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .with_context(|| format!("unable to write {}", path.display()))
+}
+
+/// Where a screenshot of a ROM at `frame` goes by default, next to the ROM
+/// itself: `game.ch8` at frame 120 saves to `game-120.png`.
+pub fn screenshot_path(rom_path: &str, frame: u64) -> PathBuf {
+    let path = Path::new(rom_path);
+    let stem = path
+        .file_stem()
+        .map_or("screenshot".into(), |stem| stem.to_string_lossy());
+    path.with_file_name(format!("{}-{}.png", stem, frame))
+}
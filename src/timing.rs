@@ -0,0 +1,191 @@
+use crate::instruction::Instruction;
+
+/// How long each instruction takes, and so how many run in a 60hz frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Timing {
+    /// Every instruction costs one cycle and a frame runs a fixed number of
+    /// them, however much work each one does.
+    #[default]
+    Flat,
+    /// Instructions cost roughly what the original COSMAC VIP interpreter
+    /// spent on them, in 1802 machine cycles, so a frame fits as many as
+    /// the real machine would run. Draws also wait for the next frame, as
+    /// the VIP's did.
+    CosmacVip,
+}
+
+/// Machine cycles in a frame on the VIP: the 1.76MHz clock is 8 cycles per
+/// machine cycle, so 3668 per 60hz frame. The display DMA takes 1024 of
+/// those (8 bytes for each of 128 scanlines) and the interrupt routine that
+/// runs the timers about 46 more.
+pub const VIP_CYCLES_PER_FRAME: u64 = 3668 - 1024 - 46;
+
+/// Fetching, decoding and dispatching any instruction.
+const VIP_FETCH: u32 = 40;
+
+impl Timing {
+    pub const NAMES: [&'static str; 2] = ["flat", "vip"];
+
+    /// Looks up a timing model by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<Timing> {
+        match name {
+            "flat" => Some(Timing::Flat),
+            "vip" => Some(Timing::CosmacVip),
+            _ => None,
+        }
+    }
+
+    /// The cycle budget for each frame. `instructions_per_frame` only
+    /// applies to flat timing; the VIP's budget is fixed by its clock.
+    pub fn cycles_per_frame(self, instructions_per_frame: u64) -> u64 {
+        match self {
+            Timing::Flat => instructions_per_frame,
+            Timing::CosmacVip => VIP_CYCLES_PER_FRAME,
+        }
+    }
+
+    /// Whether draws wait for the next frame regardless of the quirks.
+    pub(crate) fn waits_for_vblank(self) -> bool {
+        self == Timing::CosmacVip
+    }
+
+    /// What `instruction` costs to run with the given registers, before it
+    /// has changed them.
+    pub(crate) fn cost(self, instruction: Instruction, registers: &[u8; 16]) -> u32 {
+        match self {
+            Timing::Flat => 1,
+            Timing::CosmacVip => VIP_FETCH + vip_cost(instruction, registers),
+        }
+    }
+}
+
+fn vip_cost(instruction: Instruction, registers: &[u8; 16]) -> u32 {
+    let v = |x: u8| registers[usize::from(x)];
+    // Taken skips pay for moving the program counter past the next word
+    let skip = |taken: bool| if taken { 14 } else { 10 };
+    match instruction {
+        // Clears all 256 bytes of display memory
+        Instruction::ClearScreen => 3078,
+        Instruction::Return => 10,
+        Instruction::Jump(_) => 12,
+        Instruction::Call(_) => 26,
+        Instruction::SkipIfEqual(x, nn) => skip(v(x) == nn),
+        Instruction::SkipIfNotEqual(x, nn) => skip(v(x) != nn),
+        Instruction::SkipIfRegistersEqual(x, y) => 4 + skip(v(x) == v(y)),
+        Instruction::SkipIfRegistersNotEqual(x, y) => 4 + skip(v(x) != v(y)),
+        Instruction::SetRegister(..) => 6,
+        Instruction::AddToRegister(..) => 10,
+        // The VIP builds and runs a small 1802 routine for each of these
+        Instruction::Copy(..)
+        | Instruction::Or(..)
+        | Instruction::And(..)
+        | Instruction::Xor(..)
+        | Instruction::Add(..)
+        | Instruction::Subtract(..)
+        | Instruction::ShiftRight(..)
+        | Instruction::SubtractReversed(..)
+        | Instruction::ShiftLeft(..) => 44,
+        Instruction::SetIndex(_) => 12,
+        Instruction::JumpWithOffset(_) => 22,
+        Instruction::Random(..) => 36,
+        Instruction::Draw(x, _, n) => {
+            // Sprites off a byte boundary are shifted a bit at a time and
+            // written across two bytes of display memory, so each row costs
+            // more the further it is from alignment
+            let shift = u32::from(v(x) & 7);
+            let row = if shift == 0 { 26 } else { 46 + 4 * shift };
+            let rows = if n == 0 { 16 * 2 } else { u32::from(n) };
+            26 + rows * row
+        }
+        Instruction::SkipIfKey(_) | Instruction::SkipIfNotKey(_) => 14,
+        Instruction::GetDelayTimer(_) => 10,
+        Instruction::GetKey(_) => 8,
+        Instruction::SetDelayTimer(_) | Instruction::SetSoundTimer(_) => 10,
+        Instruction::AddToIndex(_) | Instruction::Font(_) => 16,
+        Instruction::BinaryConversion(x) => {
+            // Each digit is found by repeated subtraction
+            let value = v(x);
+            let digits = u32::from(value / 100 + value / 10 % 10 + value % 10);
+            80 + 16 * digits
+        }
+        Instruction::StoreMemory(x) | Instruction::LoadMemory(x) => 14 + 14 * (u32::from(x) + 1),
+        // Anything the VIP didn't have gets the cost of a simple instruction
+        _ => 12,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::NullKeypad;
+    use crate::Cpu;
+
+    fn vip(rom: &[u16]) -> Cpu {
+        let bytes: Vec<u8> = rom.iter().flat_map(|word| word.to_be_bytes()).collect();
+        Cpu::new()
+            .with_timing(Timing::CosmacVip)
+            .load_rom(&bytes)
+            .unwrap()
+    }
+
+    /// Spends one frame's cycle budget and then ticks the timers, returning
+    /// how many ticks the budget lasted.
+    fn run_frame(cpu: &mut Cpu) -> u64 {
+        let mut cycles = 0;
+        let mut ticks = 0;
+        while cycles < VIP_CYCLES_PER_FRAME {
+            cpu.tick(&mut NullKeypad).unwrap();
+            cycles += u64::from(cpu.cycles());
+            ticks += 1;
+        }
+        cpu.tick_timers();
+        ticks
+    }
+
+    #[test]
+    fn costs_include_the_fetch() {
+        let registers = [0; 16];
+        let cost = |word| Timing::CosmacVip.cost(Instruction::decode(word), &registers);
+        assert_eq!(cost(0x6001), 46);
+        assert_eq!(cost(0x7001), 50);
+        assert_eq!(cost(0x1200), 52);
+        assert_eq!(cost(0x8014), 84);
+        // 3XNN skips when v0 == 0
+        assert_eq!(cost(0x3000), 54);
+        assert_eq!(cost(0x3001), 50);
+        assert_eq!(
+            Timing::Flat.cost(Instruction::decode(0x00E0), &registers),
+            1
+        );
+    }
+
+    #[test]
+    fn draw_cost_grows_with_rows_and_misalignment() {
+        let mut registers = [0; 16];
+        let cost =
+            |registers: &[u8; 16]| Timing::CosmacVip.cost(Instruction::decode(0xD015), registers);
+        assert_eq!(cost(&registers), 40 + 26 + 5 * 26);
+        registers[0] = 3;
+        assert_eq!(cost(&registers), 40 + 26 + 5 * (46 + 12));
+    }
+
+    #[test]
+    fn a_frame_runs_as_many_instructions_as_fit_the_budget() {
+        // Each pass round the loop costs 50 + 52 cycles, so the 2598 cycle
+        // budget runs 25 passes and then one more add to go over
+        let mut cpu = vip(&[0x7001, 0x1200]);
+        assert_eq!(run_frame(&mut cpu), 51);
+        assert_eq!(cpu.registers()[0], 26);
+    }
+
+    #[test]
+    fn draws_wait_out_the_rest_of_the_frame() {
+        let mut cpu = vip(&[0xD005, 0x7001, 0x1200]);
+        // The draw, then one cycle per tick spent waiting for vblank
+        assert_eq!(run_frame(&mut cpu), 1 + (VIP_CYCLES_PER_FRAME - 196));
+        assert_eq!(cpu.registers()[0], 0);
+
+        run_frame(&mut cpu);
+        assert!(cpu.registers()[0] > 0);
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::{Cpu, Instruction};
+
+/// The machine before an instruction runs, to diff against afterwards.
+struct Pending {
+    pc: u16,
+    word: u16,
+    registers: [u8; 16],
+}
+
+/// Logs executed instructions, one line each: the address, raw word and
+/// mnemonic, then I and the timers afterwards and any registers that
+/// changed.
+///
+/// Call [`before`](Tracer::before) and [`after`](Tracer::after) around every
+/// [`Cpu::tick`]. Ticks that only wait for a frame or a key aren't logged.
+/// In ring mode only the last N lines are kept, and they're written out by
+/// [`flush`](Tracer::flush), which frontends call when the debugger stops,
+/// or when the tracer is dropped during a panic.
+pub struct Tracer {
+    output: Box<dyn Write>,
+    range: Option<RangeInclusive<u16>>,
+    last: Option<usize>,
+    buffer: VecDeque<String>,
+    pending: Option<Pending>,
+}
+
+impl Tracer {
+    pub fn new(output: impl Write + 'static) -> Self {
+        Tracer {
+            output: Box::new(output),
+            range: None,
+            last: None,
+            buffer: VecDeque::new(),
+            pending: None,
+        }
+    }
+
+    /// Only logs instructions with addresses in `range`.
+    pub fn with_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Keeps just the last `count` lines until [`flush`](Tracer::flush).
+    pub fn with_ring(mut self, count: usize) -> Self {
+        self.last = Some(count);
+        self
+    }
+
+    pub fn before(&mut self, cpu: &Cpu) {
+        let pc = cpu.pc();
+        let in_range = self.range.as_ref().is_none_or(|range| range.contains(&pc));
+        self.pending = (in_range && !cpu.is_waiting()).then(|| Pending {
+            pc,
+            word: cpu.current_instruction(),
+            registers: *cpu.registers(),
+        });
+    }
+
+    pub fn after(&mut self, cpu: &Cpu) -> io::Result<()> {
+        let Some(pending) = self.pending.take() else {
+            return Ok(());
+        };
+        let deltas: Vec<String> = pending
+            .registers
+            .iter()
+            .zip(cpu.registers())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(x, (before, after))| format!("v{:x} {:02x}->{:02x}", x, before, after))
+            .collect();
+        let line = format!(
+            "{:04x}  {:04x}  {:<24} i {:04x}  dt {:02x}  st {:02x}  {}",
+            pending.pc,
+            pending.word,
+            Instruction::decode(pending.word).to_string(),
+            cpu.index(),
+            cpu.delay_timer(),
+            cpu.sound_timer(),
+            deltas.join(" "),
+        );
+        let line = line.trim_end();
+        match self.last {
+            Some(count) => {
+                if self.buffer.len() == count {
+                    self.buffer.pop_front();
+                }
+                if count > 0 {
+                    self.buffer.push_back(line.to_string());
+                }
+                Ok(())
+            }
+            None => writeln!(self.output, "{}", line),
+        }
+    }
+
+    /// Writes out any lines held back in ring mode.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for line in self.buffer.drain(..) {
+            writeln!(self.output, "{}", line)?;
+        }
+        self.output.flush()
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        // A crash is exactly when the last few instructions matter, and
+        // the one that was running when it happened matters most
+        if std::thread::panicking() {
+            if let Some(pending) = self.pending.take() {
+                self.buffer.push_back(format!(
+                    "{:04x}  {:04x}  {:<24} crashed",
+                    pending.pc,
+                    pending.word,
+                    Instruction::decode(pending.word).to_string(),
+                ));
+            }
+            let _ = self.flush();
+        }
+    }
+}
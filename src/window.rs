@@ -0,0 +1,337 @@
+use crate::args::Args;
+use crate::audio::AudioPlayer;
+use crate::config::Config;
+use crate::console::{Console, Control};
+use crate::display::DisplayMode;
+use crate::gamepad::Gamepads;
+use crate::input::SdlKeypad;
+use crate::palette::Palette;
+use crate::renderer::{open_window, Renderer};
+use crate::rom::{create_tracer, load_state, read_movie, read_rom};
+use crate::screenshot::{screenshot_path, write_png};
+use anyhow::{Context, Error, Result};
+use chip8_core::keypad::Keypad;
+use chip8_core::movie::Movie;
+use chip8_core::rewind::Rewind;
+use chip8_core::{Cpu, FaultPolicy, Random};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Mod, Scancode};
+use std::fs::write;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+pub fn run(args: Args) -> Result<()> {
+    let rom = read_rom(&args.path)?;
+    let config = Config::load(args.config.as_deref(), &args.path)?;
+    let movie = args.play_movie.as_deref().map(read_movie).transpose()?;
+    let (mut cpu, cycles_per_frame) = match &movie {
+        Some(movie) => (movie.cpu(&rom)?, u64::from(movie.cycles_per_frame)),
+        None => (
+            Cpu::new()
+                .with_quirks(args.quirks)
+                .with_random(Random::xorshift(args.seed))
+                .with_timing(args.timing)
+                .load_rom(&rom)?,
+            args.cycles_per_frame(),
+        ),
+    };
+    cpu = cpu.with_fault_policy(match args.on_fault {
+        // The debugger stops on faults instead, so they can be looked into
+        FaultPolicy::Halt if args.debug => FaultPolicy::Warn,
+        policy => policy,
+    });
+    if let Some(path) = &args.load_state {
+        load_state(&mut cpu, path.as_ref())?;
+    }
+    let quick_save = quick_save_path(&args.path);
+    let movie_frames = movie.as_ref().map_or(0, Movie::len) as u64;
+    let mut playback = movie.as_ref().map(Movie::keypad);
+    let mut recording = args.record_movie.as_ref().map(|path| {
+        (
+            path,
+            Movie::new(args.seed, args.quirks, args.timing, cycles_per_frame as u32),
+        )
+    });
+
+    let sdl_context = sdl2::init().map_err(Error::msg)?;
+    let palettes = palettes(&args.palette);
+    let mut palette = palettes
+        .iter()
+        .position(|palette| *palette == args.palette)
+        .unwrap_or(0);
+    let canvas = open_window(&sdl_context, args.scale)?;
+    let texture_creator = canvas.texture_creator();
+    let mut renderer = Renderer::new(
+        &sdl_context,
+        canvas,
+        &texture_creator,
+        args.palette.clone(),
+        args.display,
+    );
+    let mut audio_player = AudioPlayer::new(&sdl_context).map_err(Error::msg)?;
+
+    let mut event_pump = renderer.event_pump();
+    let mut keypad = SdlKeypad::new(config.key_map);
+    let mut gamepads = Gamepads::new(&sdl_context, config.button_map)?;
+    let mut console = args.debug.then(Console::new);
+    let mut tracer = match &args.trace {
+        Some(path) => Some(create_tracer(
+            path,
+            args.trace_range.clone(),
+            args.trace_last,
+        )?),
+        None => None,
+    };
+    let mut rewind = Rewind::default();
+    let mut cycle: u64 = 0;
+    let mut frame: u64 = 0;
+    let mut next_frame = Instant::now();
+    let mut fault = None;
+    // Whether the screen has changed since it was last shown
+    let mut dirty = false;
+
+    // Everything runs on this thread in 60hz frames: a budget of cycles
+    // spent on instructions, then one timer tick, one render and a wait for the next
+    // frame. Timers and instructions stay in step however the OS schedules us
+    'running: loop {
+        // Loading a state or rewinding would break the link between a
+        // movie's frames and the run it was recorded from
+        let in_movie = recording.is_some() || playback.is_some();
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::KeyDown {
+                    scancode: Some(Scancode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown {
+                    scancode: Some(Scancode::F1),
+                    ..
+                } => {
+                    if let Some(console) = console.as_mut() {
+                        console.pause();
+                    }
+                }
+                Event::KeyDown {
+                    scancode: Some(Scancode::Return),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    if let Err(error) = renderer.toggle_fullscreen() {
+                        eprintln!("unable to toggle fullscreen: {:#}", error);
+                    }
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..),
+                    ..
+                } => renderer.redraw(),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F2),
+                    repeat: false,
+                    ..
+                } => {
+                    palette = (palette + 1) % palettes.len();
+                    renderer.set_palette(palettes[palette].clone());
+                    renderer.draw_screen(&cpu.screen);
+                    println!("palette {}", palettes[palette].name);
+                }
+                Event::KeyDown {
+                    scancode: Some(Scancode::F12),
+                    repeat: false,
+                    ..
+                } => {
+                    let path = screenshot_path(&args.path, frame);
+                    match write_png(&path, &cpu.screen, &palettes[palette], args.scale) {
+                        Ok(()) => println!("saved screenshot to {}", path.display()),
+                        Err(error) => eprintln!("{:#}", error),
+                    }
+                }
+                Event::KeyDown {
+                    scancode: Some(Scancode::F5),
+                    repeat: false,
+                    ..
+                } => match save_state(&cpu, &quick_save) {
+                    Ok(()) => println!("saved state to {}", quick_save.display()),
+                    Err(error) => eprintln!("{:#}", error),
+                },
+                Event::KeyDown {
+                    scancode: Some(Scancode::F9),
+                    repeat: false,
+                    ..
+                } if in_movie => eprintln!("can't load a state while a movie is running"),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F9),
+                    repeat: false,
+                    ..
+                } => match load_state(&mut cpu, &quick_save) {
+                    Ok(()) => {
+                        // Frames from before the load would rewind into a
+                        // different timeline
+                        rewind.clear();
+                        renderer.draw_screen(&cpu.screen);
+                        println!("loaded state from {}", quick_save.display())
+                    }
+                    Err(error) => eprintln!("{:#}", error),
+                },
+                Event::ControllerDeviceAdded { which, .. } => gamepads.add(which),
+                Event::ControllerDeviceRemoved { which, .. } => gamepads.remove(which),
+                _ => (),
+            }
+        }
+
+        // While Backspace is held step back a frame instead of running one
+        let rewinding = !in_movie
+            && event_pump
+                .keyboard_state()
+                .is_scancode_pressed(Scancode::Backspace);
+        if rewinding {
+            rewind.step_back(&mut cpu);
+            renderer.draw_screen(&cpu.screen);
+            audio_player.stop_beep();
+            wait_for_frame(&mut next_frame);
+            continue 'running;
+        }
+
+        // A new frame starts once the last one's budget has run. The debugger
+        // can stop partway through, in which case the frame carries on later
+        if cycle >= frame * cycles_per_frame {
+            // Input only changes between frames, so a movie of per-frame key
+            // states replays exactly
+            match playback.as_mut() {
+                Some(movie_keypad) if frame < movie_frames => {
+                    if frame > 0 {
+                        movie_keypad.advance();
+                    }
+                }
+                Some(_) => {
+                    playback = None;
+                    println!("movie finished, handing input back to the keyboard");
+                    keypad.update(&event_pump, &gamepads);
+                }
+                None => keypad.update(&event_pump, &gamepads),
+            }
+            if let Some((_, movie)) = recording.as_mut() {
+                movie.record(keypad.pressed());
+            }
+            rewind.record(&cpu);
+            frame += 1;
+        }
+
+        let input: &mut dyn Keypad = match playback.as_mut() {
+            Some(movie_keypad) => movie_keypad,
+            None => &mut keypad,
+        };
+        while cycle < frame * cycles_per_frame {
+            if let Some(console) = console.as_mut() {
+                match console.poll(&cpu) {
+                    Control::Run => (),
+                    Control::Wait => {
+                        // Show what led up to the stop
+                        if let Some(tracer) = tracer.as_mut() {
+                            tracer.flush().context("unable to write trace")?;
+                        }
+                        break;
+                    }
+                    Control::Quit => break 'running,
+                }
+            }
+            if let Some(tracer) = tracer.as_mut() {
+                tracer.before(&cpu);
+            }
+            let result = cpu.tick(input);
+            if let Some(tracer) = tracer.as_mut() {
+                tracer.after(&cpu).context("unable to write trace")?;
+            }
+            if let Err(error) = result {
+                if cpu.halted() {
+                    // Show what led up to the fault
+                    if let Some(tracer) = tracer.as_mut() {
+                        tracer.flush().context("unable to write trace")?;
+                    }
+                    fault = Some(error);
+                } else if let Some(console) = console.as_mut() {
+                    println!("{}", error);
+                    console.pause();
+                } else {
+                    eprintln!("warning: {}", error);
+                }
+            }
+            cycle += u64::from(cpu.cycles());
+            dirty |= cpu.should_draw();
+            if cpu.halted() {
+                break 'running;
+            }
+        }
+        // Checked before the timers tick, which ends the wait
+        let settled = !cpu.awaiting_vblank();
+        if cycle >= frame * cycles_per_frame {
+            cpu.tick_timers();
+        }
+
+        let show = match args.display {
+            // Keep showing the last frame that finished drawing
+            DisplayMode::Vblank => dirty && settled,
+            mode => dirty || mode.animates(),
+        };
+        if show {
+            renderer.draw_screen(&cpu.screen);
+            dirty = false;
+        }
+
+        if cpu.should_beep() {
+            audio_player.set_pattern(cpu.audio_pattern(), cpu.pitch());
+            audio_player.beep();
+        } else {
+            audio_player.stop_beep();
+        }
+
+        wait_for_frame(&mut next_frame);
+    }
+
+    if let Some((path, movie)) = recording {
+        write(path, movie.to_string()).with_context(|| format!("unable to write {}", path))?;
+        println!("recorded {} frames to {}", movie.len(), path);
+    }
+    match fault {
+        Some(error) => Err(Error::new(error).context("the ROM crashed")),
+        None => Ok(()),
+    }
+}
+
+/// Sleeps until the next 60hz frame is due. Deadlines advance by a fixed
+/// step so small oversleeps don't add up, but after a long stall the clock
+/// resets rather than racing to catch up.
+fn wait_for_frame(next_frame: &mut Instant) {
+    let now = Instant::now();
+    *next_frame += FRAME;
+    if *next_frame > now {
+        sleep(*next_frame - now);
+    } else if now - *next_frame > FRAME * 4 {
+        *next_frame = now;
+    }
+}
+
+/// Where F5 and F9 save and load state for a ROM, next to the ROM itself.
+fn quick_save_path(rom_path: &str) -> PathBuf {
+    Path::new(rom_path).with_extension("state")
+}
+
+fn save_state(cpu: &Cpu, path: &Path) -> Result<()> {
+    write(path, cpu.save_state()).with_context(|| format!("unable to write {}", path.display()))
+}
+
+/// Palettes F2 steps through: the one picked on the command line first if
+/// it isn't a preset, then the presets.
+fn palettes(first: &Palette) -> Vec<Palette> {
+    let presets = Palette::PRESETS
+        .iter()
+        .filter_map(|&name| Palette::preset(name));
+    if Palette::PRESETS.contains(&first.name.as_str()) {
+        presets.collect()
+    } else {
+        std::iter::once(first.clone()).chain(presets).collect()
+    }
+}